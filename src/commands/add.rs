@@ -0,0 +1,65 @@
+use super::Command;
+use crate::util::{dotfiles, git::DEFAULT_COMMIT_AUTHOR};
+use crate::{config::Config, success};
+use anyhow::Result;
+use clap::Args;
+use log::{info, warn};
+use std::path::PathBuf;
+
+const LONG_ABOUT: &str = "Start tracking files from the home directory by copying them into the \
+    stage at their path relative to the home directory. The files are registered as applied, \
+    so they are managed by subsequent applies. Directories are added recursively while files \
+    ignored by the .dotrsignore file in the root of the stage are skipped.\n\
+    \n\
+    When template suffixes are configured in the repository, files added with --template get \
+    the first configured suffix appended in stage.";
+
+/// Start tracking files from the home directory
+#[derive(Args)]
+#[command(long_about = LONG_ABOUT)]
+pub struct Add {
+    /// The files or directories to add
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Add the files as templates
+    #[arg(short, long)]
+    template: bool,
+
+    /// Commit and push the changes in stage after adding the files
+    #[arg(short, long)]
+    update: bool,
+}
+
+impl Command for Add {
+    fn run(&self, cfg: &Config) -> Result<()> {
+        let _lock = cfg.lock()?;
+
+        let mut added = 0;
+
+        for path in &self.paths {
+            for e in dotfiles::add(cfg, path, self.template)? {
+                let source = e.source.unwrap_or_default();
+                info!(
+                    "{} -> {}",
+                    e.path.to_string_lossy(),
+                    source.to_string_lossy()
+                );
+                added += 1;
+            }
+        }
+
+        if added == 0 {
+            warn!("No files have been added to the stage.");
+            return Ok(());
+        }
+
+        success!(&format!("{added} file(s) have been added to the stage."));
+
+        if self.update && dotfiles::update(cfg, DEFAULT_COMMIT_AUTHOR, None::<&str>)? {
+            success!("Dotfiles stage changes have been published to remote repository.");
+        }
+
+        Ok(())
+    }
+}
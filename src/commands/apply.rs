@@ -1,14 +1,31 @@
-use super::Command;
-use crate::{config::Config, success, util::dotfiles};
+use super::{print_plan, Command};
+use crate::mode::DeployMode;
+use crate::util::dotfiles::{self, ApplyOptions, ConflictPolicy, Failure};
+use crate::{config::Config, success};
 use anyhow::Result;
 use clap::Args;
+use yansi::Paint;
 
 const LONG_ABOUT: &str = "Apply dotfiles from stage to home directory and apply templates \
     according to the selected profile. If no profile is selected, the already applied profile \
-    will be re-applied. If no profile has been applied before and no profile is selected, no \
-    profile will be applied to the dotfiles. \n\
+    will be re-applied. If no profile has been applied before and no profile is selected, the \
+    dotfiles are rendered with the default profile. \n\
     \n\
-    When variables are missing in a profile for the templates in stage, the operation will fail.";
+    In the symlink mode, files which are not templates are linked from the home directory to \
+    the stage, so that changes made to them in the home directory directly land in the stage. \
+    Templates are still written as rendered copies. As the profile, the mode is remembered for \
+    subsequent applies.\n\
+    \n\
+    With --dry-run, the dotfiles are only rendered and the changes to the home directory are \
+    printed without touching any files. This is the same as running the plan command.\n\
+    \n\
+    Tracked files which have been modified in the home directory since they have been applied \
+    are not overwritten by default and the operation will fail. Use --conflict to back them up, \
+    overwrite or skip them instead.\n\
+    \n\
+    When variables are missing in a profile for the templates in stage, the operation will fail. \
+    When a file fails to render or write, all changes are rolled back. With --keep-going, all \
+    files which can be applied are applied and the failed files are listed at the end instead.";
 
 /// Apply dotfiles from stage to home directory and apply templates
 #[derive(Args)]
@@ -17,12 +34,59 @@ pub struct Apply {
     // The profile to be applied
     #[arg(short, long)]
     profile: Option<String>,
+
+    /// The way dotfiles are deployed to the home directory
+    #[arg(short, long)]
+    mode: Option<DeployMode>,
+
+    /// How to handle tracked files which have been modified in the home directory
+    #[arg(long, value_enum, default_value_t)]
+    conflict: ConflictPolicy,
+
+    /// Only show which files would be created, modified or deleted
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Apply all files which can be applied instead of stopping on the first error
+    #[arg(short, long)]
+    keep_going: bool,
 }
 
 impl Command for Apply {
     fn run(&self, cfg: &Config) -> Result<()> {
-        dotfiles::apply(cfg, self.profile.as_ref())?;
-        success!("Dotfiles applied from stage.");
+        let opts = ApplyOptions {
+            profile: self.profile.clone(),
+            mode: self.mode,
+            conflict: self.conflict,
+            keep_going: self.keep_going,
+        };
+
+        if self.dry_run {
+            return print_plan(&dotfiles::plan(cfg, &opts)?);
+        }
+
+        let _lock = cfg.lock()?;
+        let report = dotfiles::apply(cfg, &opts)?;
+        if !report.failures.is_empty() {
+            print_failures(&report.failures);
+            anyhow::bail!("{} file(s) could not be applied", report.failures.len());
+        }
+
+        success!(&format!(
+            "Dotfiles applied from stage ({} written, {} unchanged, {} deleted).",
+            report.written, report.unchanged, report.deleted
+        ));
         Ok(())
     }
 }
+
+pub fn print_failures(failures: &[Failure]) {
+    let paths: Vec<_> = failures.iter().map(|f| f.path.to_string_lossy()).collect();
+    let width = paths.iter().map(|p| p.len()).max().unwrap_or_default();
+
+    println!("\n{:width$}  {}", "FILE".bold(), "ERROR".bold());
+    for (path, f) in paths.iter().zip(failures) {
+        println!("{:width$}  {}", path.red(), f.error);
+    }
+    println!();
+}
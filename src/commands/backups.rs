@@ -0,0 +1,104 @@
+use super::Command;
+use crate::filecache::{FileCache, NAME_FILECACHE};
+use crate::util::backup::{Backup, ORIGINALS};
+use crate::{config::Config, success};
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use log::warn;
+use std::path::{self, PathBuf};
+use yansi::Paint;
+
+const LONG_ABOUT: &str = "Inspect and restore backups of files which have been overwritten by \
+    dotrs. Files which existed in the home directory before a dotfile has been applied to their \
+    path for the first time are kept in the 'originals' backup and are restored automatically \
+    when the dotfile is removed again, i.e. by the clean command. Locally modified files which \
+    are backed up with 'apply --conflict backup' are kept in a backup named after the time of \
+    the apply.";
+
+/// Inspect and restore backups of overwritten files
+#[derive(Args)]
+#[command(long_about = LONG_ABOUT)]
+pub struct Backups {
+    #[command(subcommand)]
+    command: BackupsCommand,
+}
+
+#[derive(Subcommand)]
+enum BackupsCommand {
+    /// List all backed up files
+    #[command(visible_aliases = ["ls"])]
+    List,
+
+    /// Restore a backed up file to its original location
+    Restore {
+        /// The original path of the file
+        path: PathBuf,
+
+        /// The backup to restore the file from; defaults to the newest backup
+        /// containing the file
+        #[arg(short, long)]
+        from: Option<String>,
+    },
+}
+
+impl Command for Backups {
+    fn run(&self, cfg: &Config) -> Result<()> {
+        let state_dir = cfg.state_dir()?;
+
+        match self.command {
+            BackupsCommand::List => {
+                let backups = Backup::all(&state_dir)?;
+                if backups.is_empty() {
+                    warn!("No backups.");
+                    return Ok(());
+                }
+
+                for backup in backups {
+                    let id = backup.id();
+                    let title = match id.as_str() {
+                        ORIGINALS => format!("{id} (files which existed before dotrs)"),
+                        _ => id,
+                    };
+                    println!("{}", title.bold());
+                    for f in backup.files()? {
+                        println!("  {}", f.to_string_lossy());
+                    }
+                }
+            }
+            BackupsCommand::Restore { ref path, ref from } => {
+                let _lock = cfg.lock()?;
+
+                let path = path::absolute(path)?;
+                let backup = match from {
+                    Some(id) => Backup::open(&state_dir, id),
+                    None => match Backup::all(&state_dir)?
+                        .into_iter()
+                        .find(|b| b.contains(&path))
+                    {
+                        Some(backup) => backup,
+                        None => anyhow::bail!("no backup contains {path:?}"),
+                    },
+                };
+
+                backup.restore(&path)?;
+
+                let fc = FileCache::open(state_dir.join(NAME_FILECACHE))?;
+                if fc.get().iter().any(|e| e.path == path) {
+                    warn!(
+                        "{} is managed by dotrs and will be overwritten by the next apply \
+                        unless it is forgotten",
+                        path.to_string_lossy()
+                    );
+                }
+
+                success!(&format!(
+                    "{} has been restored from backup {}.",
+                    path.to_string_lossy(),
+                    backup.id()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
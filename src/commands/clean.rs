@@ -3,11 +3,11 @@ use crate::{
     config::Config,
     filecache::{FileCache, NAME_FILECACHE},
     success,
+    util::dotfiles,
 };
 use anyhow::Result;
 use clap::Args;
 use log::{debug, error};
-use std::{fs, path::Path};
 
 /// Removes applied dotfiles from the home directory
 #[derive(Args)]
@@ -19,38 +19,34 @@ pub struct Clean {
 
 impl Command for Clean {
     fn run(&self, cfg: &Config) -> Result<()> {
-        let mut fc = FileCache::open(cfg.cache_dir.as_ref().join(NAME_FILECACHE))?;
+        let _lock = cfg.lock()?;
 
-        let mut failed = vec![];
+        let mut fc = FileCache::open(cfg.state_dir()?.join(NAME_FILECACHE))?;
 
-        for f in fc.get() {
-            debug!("delete {f:?}");
-            if let Err(err) = fs::remove_file(f) {
-                failed.push((f, err));
-            }
+        let mut failed = vec![];
 
-            if let Some(parent) = f.parent() {
-                if is_empty(parent)? {
-                    debug!("delete {parent:?}/");
-                    if let Err(err) = fs::remove_dir(parent) {
-                        error!("failed deleting empty directory {parent:?}: {err}")
-                    }
-                }
+        for e in fc.get() {
+            debug!("delete {:?}", e.path);
+            match dotfiles::remove_entry(e, false) {
+                Ok(()) => dotfiles::discard_original(e),
+                Err(err) => failed.push((e.clone(), err)),
             }
         }
 
         if !self.force && !failed.is_empty() {
             error!("Some cleanup operations failed:");
-            for (p, err) in &failed {
-                error!("  - {}: {}", p.to_string_lossy(), err);
+            for (e, err) in &failed {
+                error!("  - {}: {}", e.path.to_string_lossy(), err);
             }
         }
 
         match self.force {
             true => fc.clear(),
-            false => fc.set(failed.into_iter().map(|(p, _)| p.clone()).collect()),
+            false => fc.set(failed.into_iter().map(|(e, _)| e).collect()),
         }
 
+        dotfiles::prune_dirs(&mut fc);
+
         fc.store()?;
 
         success!("Dotfiles have been removed from home directory.");
@@ -58,7 +54,3 @@ impl Command for Clean {
         Ok(())
     }
 }
-
-fn is_empty(p: &Path) -> Result<bool> {
-    Ok(p.read_dir()?.next().is_none())
-}
@@ -0,0 +1,97 @@
+use super::Command;
+use crate::config::Config;
+use crate::util::{
+    diff,
+    dotfiles::{self, as_text, Action, ApplyOptions},
+};
+use anyhow::Result;
+use clap::Args;
+use log::warn;
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{self, Path, PathBuf},
+};
+use yansi::Paint;
+
+const LONG_ABOUT: &str = "Show the differences between the dotfiles rendered from stage and the \
+    current files in the home directory. Files which would be created or removed by the next \
+    apply are shown as well. If no profile is selected, the applied profile is used.";
+
+/// Show differences between the rendered stage and the home directory
+#[derive(Args)]
+#[command(long_about = LONG_ABOUT)]
+pub struct Diff {
+    /// Only show differences of these files or directories in the home directory
+    paths: Vec<PathBuf>,
+
+    /// The profile to render the stage with
+    #[arg(short, long)]
+    profile: Option<String>,
+}
+
+impl Command for Diff {
+    fn run(&self, cfg: &Config) -> Result<()> {
+        let opts = ApplyOptions {
+            profile: self.profile.clone(),
+            ..Default::default()
+        };
+        let plan = dotfiles::plan(cfg, &opts)?;
+
+        let paths = self
+            .paths
+            .iter()
+            .map(path::absolute)
+            .collect::<Result<Vec<_>, _>>()?;
+        let selected = |p: &Path| paths.is_empty() || paths.iter().any(|s| p.starts_with(s));
+
+        for action in plan.actions()? {
+            match action {
+                Action::Create(f) if selected(&f.target) => {
+                    print_diff(&[], &f.content, "/dev/null", &f.source.to_string_lossy())
+                }
+                Action::Modify(f, ref current) if selected(&f.target) => print_diff(
+                    current,
+                    &f.content,
+                    &f.target.to_string_lossy(),
+                    &f.source.to_string_lossy(),
+                ),
+                Action::Chmod(f, current) if selected(&f.target) => {
+                    let mode = |m: Option<u32>| m.map_or("-".into(), |m| format!("{m:o}"));
+                    println!("{}", format!("--- {}", f.target.to_string_lossy()).bold());
+                    println!("{}", format!("+++ {}", f.source.to_string_lossy()).bold());
+                    println!("{}", format!("-mode {}", mode(current)).red());
+                    println!("{}", format!("+mode {}", mode(f.mode)).green());
+                }
+                Action::Delete(p) if selected(p) => {
+                    let current = match fs::read(p) {
+                        Ok(current) => current,
+                        Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                        Err(err) => return Err(err.into()),
+                    };
+                    print_diff(&current, &[], &p.to_string_lossy(), "/dev/null")
+                }
+                _ => {}
+            }
+        }
+
+        for p in &plan.conflicts {
+            if selected(p) {
+                warn!("{} has been modified locally", p.to_string_lossy());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn print_diff(old: &[u8], new: &[u8], old_name: &str, new_name: &str) {
+    match (as_text(old), as_text(new)) {
+        (Some(old), Some(new)) => print!("{}", diff::unified(old, new, old_name, new_name)),
+        _ => {
+            println!("{}", format!("--- {old_name}").bold());
+            println!("{}", format!("+++ {new_name}").bold());
+            println!("{}", "binary files differ".dim());
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use super::Command;
+use crate::config::Config;
+use crate::facts::{Facts as MachineFacts, FACTS_KEY};
+use anyhow::Result;
+use clap::Args;
+use yansi::Paint;
+
+const LONG_ABOUT: &str = "Print the facts about this machine which are available in templates \
+    under the 'dotrs' key, i.e. '{{dotrs.host}}'. Facts take precedence over values of the \
+    same name in profiles. Facts which are not available on this machine are empty.";
+
+/// Print the machine facts available in templates
+#[derive(Args)]
+#[command(long_about = LONG_ABOUT)]
+pub struct Facts;
+
+impl Command for Facts {
+    fn run(&self, cfg: &Config) -> Result<()> {
+        for (name, value) in MachineFacts::collect(&cfg.target_dir()?)?.entries() {
+            println!(
+                "{:<14} {}",
+                format!("{FACTS_KEY}.{name}").bold(),
+                value.unwrap_or("-")
+            );
+        }
+        Ok(())
+    }
+}
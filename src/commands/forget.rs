@@ -0,0 +1,49 @@
+use super::Command;
+use crate::util::{dotfiles, git::DEFAULT_COMMIT_AUTHOR};
+use crate::{config::Config, success};
+use anyhow::Result;
+use clap::Args;
+use log::info;
+use std::path::PathBuf;
+
+const LONG_ABOUT: &str = "Stop managing applied dotfiles without deleting them from the home \
+    directory. The source files are removed from the stage and the removal is committed, while \
+    the files in the home directory are left in place and become machine-local. Directories \
+    forget all tracked files below them.\n\
+    \n\
+    The commit is published with the next update, either via the update command or by the \
+    service.";
+
+/// Stop managing dotfiles without deleting them from the home directory
+#[derive(Args)]
+#[command(long_about = LONG_ABOUT)]
+pub struct Forget {
+    /// The files or directories in the home directory to forget
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Commit author
+    #[arg(short, long, default_value = DEFAULT_COMMIT_AUTHOR)]
+    author: String,
+}
+
+impl Command for Forget {
+    fn run(&self, cfg: &Config) -> Result<()> {
+        let _lock = cfg.lock()?;
+
+        let mut forgotten = 0;
+
+        for path in &self.paths {
+            for e in dotfiles::forget(cfg, path, &self.author)? {
+                info!("{}", e.path.to_string_lossy());
+                forgotten += 1;
+            }
+        }
+
+        success!(&format!(
+            "{forgotten} file(s) are no longer managed by dotrs."
+        ));
+
+        Ok(())
+    }
+}
@@ -19,6 +19,8 @@ pub struct Import {
 
 impl Command for Import {
     fn run(&self, cfg: &Config) -> Result<()> {
+        let _lock = cfg.lock()?;
+
         import_from_git(cfg, &self.uri, &self.branch)?;
         Ok(())
     }
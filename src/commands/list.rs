@@ -4,15 +4,20 @@ use crate::filecache::{FileCache, NAME_FILECACHE};
 use anyhow::Result;
 use clap::Args;
 use log::warn;
+use std::path;
 
 /// List currently applied dotfiles
 #[derive(Args)]
 #[command(aliases = ["ls"])]
-pub struct List;
+pub struct List {
+    /// Also show the mode, profile and source in stage of each file
+    #[arg(short, long)]
+    long: bool,
+}
 
 impl Command for List {
     fn run(&self, cfg: &Config) -> Result<()> {
-        let fc = FileCache::open(cfg.cache_dir.as_ref().join(NAME_FILECACHE))?;
+        let fc = FileCache::open(cfg.state_dir()?.join(NAME_FILECACHE))?;
         let entries = fc.get();
 
         if entries.is_empty() {
@@ -20,8 +25,24 @@ impl Command for List {
             return Ok(());
         }
 
+        let stage_dir = path::absolute(&cfg.stage_dir)?;
+
         for entry in entries {
-            println!("{}", entry.to_string_lossy());
+            if !self.long {
+                println!("{}", entry.path.to_string_lossy());
+                continue;
+            }
+
+            let mode = entry.mode.map_or("-".into(), |m| format!("{m:04o}"));
+            let profile = entry.profile.as_deref().unwrap_or("-");
+            let source = entry.source.as_ref().map_or("-".into(), |s| {
+                s.strip_prefix(&stage_dir).unwrap_or(s).to_string_lossy()
+            });
+            let templated = if entry.templated { " (template)" } else { "" };
+            println!(
+                "{mode:>5}  {profile:<10}  {} <- {source}{templated}",
+                entry.path.to_string_lossy()
+            );
         }
 
         Ok(())
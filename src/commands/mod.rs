@@ -1,6 +1,7 @@
 use crate::{config::Config, logging};
 use anyhow::Result;
 use log::LevelFilter;
+use std::fmt;
 
 macro_rules! re_export {
     ( $( $md:tt )+ ) => {
@@ -14,14 +15,24 @@ macro_rules! re_export {
 // List the names of your command modules to re-export them
 // in this module.
 re_export! {
+    add
     apply
+    backups
     cd
     clean
+    diff
+    facts
+    forget
     import
     list
+    plan
     pull
+    readd
+    render
     service
+    status
     update
+    which
 }
 
 pub trait Command {
@@ -32,6 +43,20 @@ pub trait Command {
     }
 }
 
+/// An error which makes dotrs exit with the given exit code without printing
+/// an error message. Used by commands which report a result through their
+/// exit code.
+#[derive(Debug)]
+pub struct Exit(pub u8);
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "exit code {}", self.0)
+    }
+}
+
+impl std::error::Error for Exit {}
+
 #[macro_export]
 macro_rules! register_commands {
     ( $( $command:tt )+ ) => {
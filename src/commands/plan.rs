@@ -0,0 +1,94 @@
+use super::{print_failures, Command};
+use crate::mode::DeployMode;
+use crate::util::{
+    diff,
+    dotfiles::{self, as_text, Action, ApplyOptions},
+};
+use crate::{config::Config, success};
+use anyhow::Result;
+use clap::Args;
+use log::{debug, warn};
+use yansi::Paint;
+
+/// Show the changes applying dotfiles would make to the home directory
+#[derive(Args)]
+pub struct Plan {
+    /// The profile to be applied
+    #[arg(short, long)]
+    profile: Option<String>,
+
+    /// The way dotfiles are deployed to the home directory
+    #[arg(short, long)]
+    mode: Option<DeployMode>,
+}
+
+impl Command for Plan {
+    fn run(&self, cfg: &Config) -> Result<()> {
+        let opts = ApplyOptions {
+            profile: self.profile.clone(),
+            mode: self.mode,
+            ..Default::default()
+        };
+        print_plan(&dotfiles::plan(cfg, &opts)?)
+    }
+}
+
+pub fn print_plan(plan: &dotfiles::Plan) -> Result<()> {
+    let (mut created, mut modified, mut deleted) = (0, 0, 0);
+
+    for action in plan.actions()? {
+        match action {
+            Action::Create(f) => {
+                created += 1;
+                println!("{} {}", "create".green().bold(), f.target.to_string_lossy());
+            }
+            Action::Modify(f, current) => {
+                modified += 1;
+                let target = f.target.to_string_lossy();
+                println!("{} {target}", "modify".yellow().bold());
+                match (as_text(&current), as_text(&f.content)) {
+                    (Some(current), Some(content)) => print!(
+                        "{}",
+                        diff::unified(current, content, &target, &f.source.to_string_lossy())
+                    ),
+                    _ => println!("{}", "binary files differ".dim()),
+                }
+            }
+            Action::Chmod(f, current) => {
+                modified += 1;
+                let mode = |m: Option<u32>| m.map_or("-".into(), |m| format!("{m:o}"));
+                println!(
+                    "{} {} ({} -> {})",
+                    "chmod".yellow().bold(),
+                    f.target.to_string_lossy(),
+                    mode(current),
+                    mode(f.mode)
+                );
+            }
+            Action::Delete(p) => {
+                deleted += 1;
+                println!("{} {}", "delete".red().bold(), p.to_string_lossy());
+            }
+            Action::Unchanged(f) => debug!("unchanged {:?}", f.target),
+        }
+    }
+
+    for p in &plan.conflicts {
+        warn!("{} has been modified locally", p.to_string_lossy());
+    }
+
+    if created + modified + deleted > 0 {
+        println!("\n{created} to create, {modified} to modify, {deleted} to delete.");
+    }
+
+    if !plan.failures.is_empty() {
+        print_failures(&plan.failures);
+        anyhow::bail!("{} file(s) could not be rendered", plan.failures.len());
+    }
+
+    if created + modified + deleted == 0 {
+        success!("Home directory is up to date with stage.");
+    }
+
+    Ok(())
+}
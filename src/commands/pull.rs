@@ -9,6 +9,8 @@ pub struct Pull;
 
 impl Command for Pull {
     fn run(&self, cfg: &Config) -> Result<()> {
+        let _lock = cfg.lock()?;
+
         dotfiles::pull(cfg)?;
         success!("Dotfiles stage has been updated from upstream.");
         Ok(())
@@ -0,0 +1,57 @@
+use super::Command;
+use crate::util::dotfiles::{self, ReAddOutcome};
+use crate::{config::Config, success};
+use anyhow::Result;
+use clap::Args;
+use log::{info, warn};
+
+const LONG_ABOUT: &str = "Capture changes made to applied dotfiles in the home directory back \
+    into the stage. Files which are not templates are copied back to the stage as they are. \
+    Changes to templates are only captured when they can be mapped cleanly onto the template, \
+    i.e. when only lines without template expressions have been changed.";
+
+/// Capture changes made to applied dotfiles in home back into the stage
+#[derive(Args)]
+#[command(long_about = LONG_ABOUT)]
+pub struct ReAdd;
+
+impl Command for ReAdd {
+    fn run(&self, cfg: &Config) -> Result<()> {
+        let _lock = cfg.lock()?;
+
+        let results = dotfiles::re_add(cfg)?;
+
+        if results.is_empty() {
+            warn!("No applied dotfiles have been modified in the home directory.");
+            return Ok(());
+        }
+
+        let mut captured = 0;
+
+        for r in &results {
+            let target = r.target.to_string_lossy();
+            let source = r
+                .source
+                .as_ref()
+                .map_or("?".into(), |p| p.to_string_lossy());
+
+            match r.outcome {
+                ReAddOutcome::Copied => info!("{target} -> {source}"),
+                ReAddOutcome::Merged => info!("{target} -> {source} (merged into template)"),
+                ReAddOutcome::Refused(reason) => warn!("{target} has not been re-added: {reason}"),
+            }
+
+            if !matches!(r.outcome, ReAddOutcome::Refused(_)) {
+                captured += 1;
+            }
+        }
+
+        if captured > 0 {
+            success!(&format!(
+                "{captured} file(s) have been re-added to the stage."
+            ));
+        }
+
+        Ok(())
+    }
+}
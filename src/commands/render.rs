@@ -0,0 +1,73 @@
+use super::Command;
+use crate::config::Config;
+use crate::profile::{get_applied_profile, Value};
+use crate::util::dotfiles::Renderer;
+use anyhow::Result;
+use clap::Args;
+use std::io::{self, Write};
+use std::path::{self, PathBuf};
+
+const LONG_ABOUT: &str = "Render a single file from the stage with the same templating \
+    configuration and profile data used by apply and print the result to stdout without \
+    writing any files. If no profile is selected, the applied profile is used.\n\
+    \n\
+    Single variables of the profile can be overridden with --var. Nested variables can be \
+    addressed with dots, i.e. '--var git.email=me@example.com'. Values are parsed as YAML, so \
+    numbers and booleans keep their types.";
+
+/// Render a single file from the stage and print it
+#[derive(Args)]
+#[command(long_about = LONG_ABOUT)]
+pub struct Render {
+    /// The file in the stage; relative paths are resolved against the stage
+    /// first and against the current directory otherwise
+    path: PathBuf,
+
+    /// The profile to render the file with
+    #[arg(short, long)]
+    profile: Option<String>,
+
+    /// Override a variable of the profile
+    #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_var)]
+    vars: Vec<(String, String)>,
+}
+
+impl Command for Render {
+    fn run(&self, cfg: &Config) -> Result<()> {
+        let profile = match self.profile {
+            Some(ref p) => Some(p.clone()),
+            None => get_applied_profile(cfg.state_dir()?)?,
+        };
+
+        let stage_dir = path::absolute(&cfg.stage_dir)?;
+        let path = match self.path.is_relative() && stage_dir.join(&self.path).is_file() {
+            true => stage_dir.join(&self.path),
+            false => path::absolute(&self.path)?,
+        };
+        if !path.starts_with(&stage_dir) {
+            anyhow::bail!("{path:?} is not located in the stage");
+        }
+        if !path.is_file() {
+            anyhow::bail!("{path:?} does not exist in the stage");
+        }
+
+        let mut renderer = Renderer::new(cfg, profile.as_deref())?;
+        for (key, value) in &self.vars {
+            let value =
+                serde_yaml::from_str(value).unwrap_or_else(|_| Value::String(value.clone()));
+            renderer.set_var(key, value);
+        }
+
+        let rendered = renderer.render(&path)?;
+        io::stdout().write_all(&rendered.content)?;
+
+        Ok(())
+    }
+}
+
+fn parse_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(format!("invalid variable '{s}', expected KEY=VALUE")),
+    }
+}
@@ -0,0 +1,166 @@
+use super::{Command, Exit};
+use crate::config::Config;
+use crate::profile::get_applied_profile;
+use crate::util::dotfiles::{self, FileState};
+use crate::util::git::Git;
+use anyhow::Result;
+use clap::Args;
+use log::debug;
+use serde::Serialize;
+use std::path::PathBuf;
+use yansi::Paint;
+
+const LONG_ABOUT: &str = "Show the synchronization state of the dotfiles. This includes the \
+    uncommitted changes in the stage, the commits the stage is ahead or behind of the upstream \
+    repository, the applied profile and the state of each dotfile in the home directory.\n\
+    \n\
+    The command exits with exit code 2 when anything is not in sync, so that scripts can tell \
+    this apart from a failure, which exits with exit code 1.";
+
+/// Show the synchronization state of stage, upstream and home directory
+#[derive(Args)]
+#[command(visible_aliases = ["st"], long_about = LONG_ABOUT)]
+pub struct Status {
+    /// Fetch the upstream repository before comparing the stage with it
+    #[arg(short, long)]
+    fetch: bool,
+
+    /// Print the status as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Report {
+    in_sync: bool,
+    profile: Option<String>,
+    stage: StageReport,
+    files: Vec<FileReport>,
+}
+
+#[derive(Serialize)]
+struct StageReport {
+    branch: String,
+    /// The number of commits which have not been pushed; unknown when the
+    /// branch does not exist in the upstream repository.
+    ahead: Option<usize>,
+    behind: Option<usize>,
+    changes: Vec<ChangeReport>,
+}
+
+#[derive(Serialize)]
+struct ChangeReport {
+    change: &'static str,
+    path: String,
+}
+
+#[derive(Serialize)]
+struct FileReport {
+    target: PathBuf,
+    state: FileState,
+}
+
+impl Command for Status {
+    fn run(&self, cfg: &Config) -> Result<()> {
+        let git = Git::new(cfg.stage_dir.as_ref());
+        let branch = git.current_branch()?;
+
+        if self.fetch {
+            git.exec(["fetch", "origin", &branch])?;
+        }
+
+        let ahead = git
+            .commits_ahead(&branch)
+            .inspect_err(|err| debug!("failed counting commits ahead: {err}"))
+            .ok();
+        let behind = git
+            .commits_behind(&branch)
+            .inspect_err(|err| debug!("failed counting commits behind: {err}"))
+            .ok();
+        let changes: Vec<_> = git
+            .changed_files()?
+            .into_iter()
+            .map(|(change, path)| ChangeReport {
+                change: change.name(),
+                path,
+            })
+            .collect();
+
+        let files: Vec<_> = dotfiles::file_states(cfg)?
+            .into_iter()
+            .map(|(target, state)| FileReport { target, state })
+            .collect();
+
+        let in_sync = changes.is_empty()
+            && ahead.unwrap_or_default() == 0
+            && behind.unwrap_or_default() == 0
+            && files.iter().all(|f| f.state == FileState::InSync);
+
+        let report = Report {
+            in_sync,
+            profile: get_applied_profile(cfg.state_dir()?)?,
+            stage: StageReport {
+                branch,
+                ahead,
+                behind,
+                changes,
+            },
+            files,
+        };
+
+        match self.json {
+            true => println!("{}", serde_json::to_string_pretty(&report)?),
+            false => print_report(&report),
+        }
+
+        if !report.in_sync {
+            return Err(Exit(2).into());
+        }
+
+        Ok(())
+    }
+}
+
+fn print_report(report: &Report) {
+    let count = |n: Option<usize>| n.map_or("?".into(), |n| n.to_string());
+
+    println!(
+        "{} {}",
+        "Profile:".bold(),
+        report.profile.as_deref().unwrap_or("-")
+    );
+    println!(
+        "{} {} ({} ahead, {} behind upstream)",
+        "Branch:".bold(),
+        report.stage.branch,
+        count(report.stage.ahead),
+        count(report.stage.behind)
+    );
+
+    if !report.stage.changes.is_empty() {
+        println!("\n{}", "Uncommitted changes in stage:".bold());
+        for c in &report.stage.changes {
+            println!("  {:<10} {}", c.change.yellow(), c.path);
+        }
+    }
+
+    if !report.files.is_empty() {
+        println!("\n{}", "Dotfiles:".bold());
+        for f in &report.files {
+            let state = match f.state {
+                FileState::InSync => "in sync".green(),
+                FileState::Modified => "modified".yellow(),
+                FileState::Missing => "missing".red(),
+                FileState::Pending => "pending".cyan(),
+                FileState::Failed => "failed".red(),
+            };
+            println!("  {state:<10} {}", f.target.to_string_lossy());
+        }
+    }
+
+    println!();
+    match report.in_sync {
+        true => println!("{}", "Everything is in sync.".green()),
+        false => println!("{}", "Not everything is in sync.".yellow()),
+    }
+}
@@ -19,6 +19,8 @@ pub struct Update {
 
 impl Command for Update {
     fn run(&self, cfg: &Config) -> Result<()> {
+        let _lock = cfg.lock()?;
+
         match dotfiles::update(cfg, &self.author, self.message.as_ref())? {
             true => {
                 success!("Dotfiles stage changes have been published to remote repository.")
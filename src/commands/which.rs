@@ -0,0 +1,73 @@
+use super::Command;
+use crate::config::Config;
+use crate::filecache::{EntryKind, FileCache, NAME_FILECACHE};
+use crate::util::{dotfiles, git::Git};
+use anyhow::Result;
+use clap::Args;
+use log::debug;
+use std::path::{self, PathBuf};
+use yansi::Paint;
+
+/// Show which file in stage a file in the home directory has been applied from
+#[derive(Args)]
+pub struct Which {
+    /// The file in the home directory
+    path: PathBuf,
+}
+
+impl Command for Which {
+    fn run(&self, cfg: &Config) -> Result<()> {
+        let path = path::absolute(&self.path)?;
+
+        let fc = FileCache::open(cfg.state_dir()?.join(NAME_FILECACHE))?;
+        let Some(e) = fc.get().iter().find(|e| e.path == path) else {
+            anyhow::bail!("{} is not managed by dotrs", path.to_string_lossy());
+        };
+
+        let stage_dir = path::absolute(&cfg.stage_dir)?;
+        let source = e
+            .source
+            .as_ref()
+            .map(|s| s.strip_prefix(&stage_dir).unwrap_or(s));
+
+        let last_commit = match source {
+            Some(source) => Git::new(&stage_dir)
+                .last_commit(source)
+                .inspect_err(|err| debug!("failed getting last commit: {err}"))
+                .ok()
+                .flatten(),
+            None => None,
+        };
+
+        let matches = if e.path.symlink_metadata().is_err() {
+            "no, the file does not exist".red()
+        } else if dotfiles::locally_modified(e)? {
+            "no, the file has been modified locally".yellow()
+        } else {
+            "yes".green()
+        };
+
+        let field = |name: &str, value: &dyn std::fmt::Display| {
+            println!("{:<12} {value}", format!("{name}:").bold())
+        };
+
+        field("File", &path.to_string_lossy());
+        field(
+            "Source",
+            &source.map_or("unknown".into(), |s| s.to_string_lossy()),
+        );
+        field(
+            "Kind",
+            &match e.kind {
+                EntryKind::File => "copy",
+                EntryKind::Symlink => "symlink",
+            },
+        );
+        field("Templated", &if e.templated { "yes" } else { "no" });
+        field("Profile", &e.profile.as_deref().unwrap_or("-"));
+        field("Last commit", &last_commit.as_deref().unwrap_or("-"));
+        field("Matches", &matches);
+
+        Ok(())
+    }
+}
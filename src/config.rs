@@ -1,8 +1,11 @@
+use crate::util::lock::Lock;
+use crate::{filecache, util};
+use anyhow::Result;
 use clap::Args;
 use log::LevelFilter;
 use std::fmt;
 use std::fmt::Formatter;
-use std::path::{Path, PathBuf};
+use std::path::{self, Path, PathBuf};
 use std::str::FromStr;
 
 macro_rules! default_home_dir {
@@ -21,7 +24,7 @@ pub struct PrintablePathBuf(PathBuf);
 impl FromStr for PrintablePathBuf {
     type Err = <PathBuf as FromStr>::Err;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         Ok(Self(PathBuf::from_str(s)?))
     }
 }
@@ -56,4 +59,41 @@ pub struct Config {
         env = "DOTRS_CACHE_DIR"
     )]
     pub cache_dir: PrintablePathBuf,
+
+    /// Apply dotfiles to this directory instead of the home directory
+    #[arg(long, env = "DOTRS_TARGET_DIR")]
+    pub target_dir: Option<PrintablePathBuf>,
+
+    /// Wait for other running dotrs operations to finish instead of failing
+    #[arg(long, env = "DOTRS_WAIT")]
+    pub wait: bool,
+}
+
+impl Config {
+    /// Returns the directory dotfiles are applied to.
+    pub fn target_dir(&self) -> Result<PathBuf> {
+        match self.target_dir {
+            Some(ref dir) => Ok(path::absolute(dir)?),
+            None => util::home_dir(),
+        }
+    }
+
+    /// Returns the directory in which the state of the applied dotfiles (i.e.
+    /// the tracked files and the applied profile) is stored. The state of each
+    /// target directory other than the home directory is kept separately.
+    pub fn state_dir(&self) -> Result<PathBuf> {
+        let target_dir = self.target_dir()?;
+        if target_dir == util::home_dir()? {
+            return Ok(self.cache_dir.0.clone());
+        }
+
+        let hash = filecache::hash(target_dir.as_os_str().as_encoded_bytes());
+        Ok(self.cache_dir.0.join("targets").join(&hash[..16]))
+    }
+
+    /// Acquires the lock which must be held by all operations modifying the
+    /// stage or the cache.
+    pub fn lock(&self) -> Result<Lock> {
+        Lock::acquire(&self.cache_dir, self.wait)
+    }
 }
@@ -0,0 +1,70 @@
+use crate::profile::Value;
+use anyhow::Result;
+use log::debug;
+use std::{collections::HashMap, env, fs, path::Path};
+
+/// The key under which the facts are available in the template data.
+pub const FACTS_KEY: &str = "dotrs";
+
+/// Facts about the machine dotfiles are applied on which are available in
+/// templates in addition to the profile data.
+pub struct Facts {
+    pub host: Option<String>,
+    pub os: String,
+    pub arch: String,
+    pub user: String,
+    /// The directory dotfiles are applied to, which is the home directory
+    /// unless it has been overridden.
+    pub home: String,
+    /// The ID of the Linux distribution from `/etc/os-release` (i.e. `ubuntu`).
+    pub distro: Option<String>,
+}
+
+impl Facts {
+    pub fn collect(target_dir: &Path) -> Result<Self> {
+        Ok(Self {
+            host: whoami::fallible::hostname()
+                .inspect_err(|err| debug!("failed getting hostname: {err}"))
+                .ok(),
+            os: env::consts::OS.to_owned(),
+            arch: env::consts::ARCH.to_owned(),
+            user: whoami::username(),
+            home: target_dir.to_string_lossy().into_owned(),
+            distro: distro(),
+        })
+    }
+
+    /// Returns the names and values of all facts.
+    pub fn entries(&self) -> Vec<(&'static str, Option<&str>)> {
+        vec![
+            ("host", self.host.as_deref()),
+            ("os", Some(self.os.as_str())),
+            ("arch", Some(self.arch.as_str())),
+            ("user", Some(self.user.as_str())),
+            ("home", Some(self.home.as_str())),
+            ("distro", self.distro.as_deref()),
+        ]
+    }
+
+    pub fn to_value(&self) -> Value {
+        let map: HashMap<_, _> = self
+            .entries()
+            .into_iter()
+            .map(|(name, value)| {
+                let value = value.map_or(Value::None, |v| Value::String(v.to_owned()));
+                (name.to_owned(), value)
+            })
+            .collect();
+        Value::Map(map)
+    }
+}
+
+fn distro() -> Option<String> {
+    let os_release = fs::read_to_string("/etc/os-release")
+        .or_else(|_| fs::read_to_string("/usr/lib/os-release"))
+        .ok()?;
+    os_release.lines().find_map(|line| {
+        let id = line.strip_prefix("ID=")?;
+        Some(id.trim_matches(|c| c == '"' || c == '\'').to_owned())
+    })
+}
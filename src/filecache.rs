@@ -1,15 +1,68 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
+    collections::HashSet,
     fs::{self, File},
     io::{read_to_string, Write},
     path::{Path, PathBuf},
-    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-pub const NAME_FILECACHE: &str = "tracked_files";
+pub const NAME_FILECACHE: &str = "manifest.json";
+
+/// The line based file cache which has been used before the manifest.
+const NAME_LEGACY_FILECACHE: &str = "tracked_files";
+
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Symlink,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(rename = "target")]
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    #[serde(default)]
+    pub mode: Option<u32>,
+    /// The SHA-256 hash of the content which has been written to the target.
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// The file in the stage the target has been applied from.
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+    /// Whether the source has been rendered as a template.
+    #[serde(default)]
+    pub templated: bool,
+    /// The profile which has been used to render the target.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// The time the target has been applied at in seconds since the unix epoch.
+    #[serde(default)]
+    pub applied_at: Option<u64>,
+    /// The copy of the file which existed at the target before it has been
+    /// applied for the first time.
+    #[serde(default)]
+    pub backup: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    files: Vec<Entry>,
+    /// Directories which did not exist before they have been created by dotrs.
+    #[serde(default)]
+    dirs: Vec<PathBuf>,
+}
 
 pub struct FileCache {
-    files: Vec<PathBuf>,
+    files: Vec<Entry>,
+    dirs: Vec<PathBuf>,
     storage_dir: PathBuf,
 }
 
@@ -22,54 +75,164 @@ pub struct Diff<T> {
 impl FileCache {
     pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self> {
         let storage_dir = path.into();
-        let files = match storage_dir.exists() {
-            true => parse(&storage_dir)?,
-            false => vec![],
+        let legacy = storage_dir.with_file_name(NAME_LEGACY_FILECACHE);
+        let (files, dirs) = if storage_dir.exists() {
+            parse(&storage_dir)?
+        } else if legacy.exists() {
+            (parse_legacy(&legacy)?, vec![])
+        } else {
+            (vec![], vec![])
         };
-        Ok(Self { storage_dir, files })
+        Ok(Self {
+            storage_dir,
+            files,
+            dirs,
+        })
     }
 
     pub fn store(&self) -> Result<()> {
-        if let Some(parent) = self.storage_dir.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent)?;
-            }
-        }
+        let dir = match self.storage_dir.parent() {
+            Some(parent) => parent,
+            None => Path::new("."),
+        };
+        fs::create_dir_all(dir)?;
+
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            files: self.files.clone(),
+            dirs: self.dirs.clone(),
+        };
 
-        let mut f = File::create(&self.storage_dir)?;
-        for entry in &self.files {
-            writeln!(f, "{}", entry.to_string_lossy())?;
+        let mut f = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer_pretty(&mut f, &manifest)?;
+        f.write_all(b"\n")?;
+        f.persist(&self.storage_dir)?;
+
+        let legacy = self.storage_dir.with_file_name(NAME_LEGACY_FILECACHE);
+        if legacy.exists() {
+            fs::remove_file(legacy)?;
         }
 
         Ok(())
     }
 
-    pub fn get(&self) -> &Vec<PathBuf> {
+    pub fn get(&self) -> &Vec<Entry> {
         &self.files
     }
 
-    pub fn set(&mut self, v: Vec<PathBuf>) {
+    pub fn set(&mut self, v: Vec<Entry>) {
         self.files = v;
     }
 
+    /// Adds the given entry or replaces the entry with the same path.
+    pub fn insert(&mut self, e: Entry) {
+        match self.files.iter_mut().find(|f| f.path == e.path) {
+            Some(f) => *f = e,
+            None => self.files.push(e),
+        }
+    }
+
+    /// Removes all tracked files. Tracked directories are kept.
     pub fn clear(&mut self) {
         self.set(vec![]);
     }
 
-    pub fn diff<'a, 'b: 'a>(&'a self, other: &'b [PathBuf]) -> Diff<&'a PathBuf> {
-        let added = other.iter().filter(|o| !self.files.contains(o)).collect();
-        let removed = self.files.iter().filter(|s| !other.contains(s)).collect();
+    pub fn dirs(&self) -> &Vec<PathBuf> {
+        &self.dirs
+    }
+
+    pub fn set_dirs(&mut self, v: Vec<PathBuf>) {
+        self.dirs = v;
+    }
+
+    /// Records directories which have been created by dotrs.
+    pub fn add_dirs<I: IntoIterator<Item = PathBuf>>(&mut self, dirs: I) {
+        let mut known: HashSet<_> = self.dirs.iter().cloned().collect();
+        for dir in dirs {
+            if known.insert(dir.clone()) {
+                self.dirs.push(dir);
+            }
+        }
+    }
+
+    pub fn diff<'a, 'b: 'a>(&'a self, other: &'b [Entry]) -> Diff<&'a Entry> {
+        let own: HashSet<_> = self.files.iter().map(|e| &e.path).collect();
+        let others: HashSet<_> = other.iter().map(|e| &e.path).collect();
+        let added = other.iter().filter(|o| !own.contains(&o.path)).collect();
+        let removed = self
+            .files
+            .iter()
+            .filter(|s| !others.contains(&s.path))
+            .collect();
         Diff { added, removed }
     }
 }
 
-fn parse(p: &Path) -> Result<Vec<PathBuf>> {
+fn parse(p: &Path) -> Result<(Vec<Entry>, Vec<PathBuf>)> {
+    let manifest: Manifest = serde_json::from_reader(File::open(p)?)?;
+    if manifest.version > MANIFEST_VERSION {
+        anyhow::bail!(
+            "{p:?} has been written by a newer version of dotrs (manifest version {})",
+            manifest.version
+        );
+    }
+    Ok((manifest.files, manifest.dirs))
+}
+
+/// Parses the line based file cache, which lists one applied path per line.
+fn parse_legacy(p: &Path) -> Result<Vec<Entry>> {
     let f = File::open(p)?;
-    let entries: Result<Vec<_>, _> = read_to_string(f)?
+    let entries = read_to_string(f)?
         .lines()
         .map(|s| s.trim())
         .filter(|s| !s.is_empty())
-        .map(PathBuf::from_str)
+        .map(|path| Entry {
+            path: PathBuf::from(path),
+            kind: EntryKind::File,
+            mode: None,
+            hash: None,
+            source: None,
+            templated: false,
+            profile: None,
+            applied_at: None,
+            backup: None,
+        })
         .collect();
-    Ok(entries?)
+    Ok(entries)
+}
+
+pub fn hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Returns the current time in seconds since the unix epoch.
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_legacy_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let p = dir.path().join(NAME_LEGACY_FILECACHE);
+        fs::write(&p, "/home/alice/.bashrc\n\n/home/alice/a\tb\n").unwrap();
+
+        let entries = parse_legacy(&p).unwrap();
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("/home/alice/.bashrc"),
+                Path::new("/home/alice/a\tb")
+            ]
+        );
+        assert!(entries
+            .iter()
+            .all(|e| e.kind == EntryKind::File && e.hash.is_none()));
+    }
 }
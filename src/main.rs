@@ -1,16 +1,20 @@
 mod commands;
 mod config;
+mod facts;
 mod filecache;
 mod logging;
+mod mode;
 mod profile;
+mod repoconfig;
 mod service;
 mod util;
 
 use anyhow::Result;
-use clap::{command, Parser};
+use clap::Parser;
 use commands::*;
 use config::Config;
 use log::LevelFilter;
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -26,17 +30,27 @@ struct Cli {
 }
 
 register_commands! {
+    Add
     Apply
     Clean
     List
+    Plan
+    Diff
+    Render
+    Status
+    Which
+    Facts
     Import
     Pull
     Update
+    ReAdd
+    Forget
+    Backups
     Cd
     StartService
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
 
     let cfg = cli.config;
@@ -47,7 +61,12 @@ fn main() -> Result<()> {
     };
 
     cli.commands.init_logger(level_filter);
-    cli.commands.run(&cfg)?;
 
-    Ok(())
+    match cli.commands.run(&cfg) {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(err) => match err.downcast_ref::<Exit>() {
+            Some(Exit(code)) => Ok(ExitCode::from(*code)),
+            None => Err(err),
+        },
+    }
 }
@@ -0,0 +1,60 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::Path,
+    str::FromStr,
+};
+
+const APPLIED_MODE_FILE: &str = ".dotrs-applied-mode";
+
+/// The way dotfiles are deployed from the stage to the home directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DeployMode {
+    /// Write rendered copies of all files
+    #[default]
+    Copy,
+    /// Symlink non-template files to the stage and write rendered copies of templates
+    Symlink,
+}
+
+impl fmt::Display for DeployMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Copy => "copy".fmt(f),
+            Self::Symlink => "symlink".fmt(f),
+        }
+    }
+}
+
+impl FromStr for DeployMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "copy" => Ok(Self::Copy),
+            "symlink" => Ok(Self::Symlink),
+            _ => Err(anyhow::anyhow!("invalid deploy mode: {s}")),
+        }
+    }
+}
+
+pub fn get_applied_mode<P: AsRef<Path>>(cache_dir: P) -> Result<Option<DeployMode>> {
+    let p = cache_dir.as_ref().join(APPLIED_MODE_FILE);
+    match fs::read_to_string(p) {
+        Ok(v) => Ok(Some(v.trim().parse()?)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub fn write_applied_mode<P: AsRef<Path>>(cache_dir: P, mode: DeployMode) -> Result<()> {
+    let cache_dir = cache_dir.as_ref();
+    if !cache_dir.exists() {
+        fs::create_dir_all(cache_dir)?;
+    }
+    File::create(cache_dir.join(APPLIED_MODE_FILE))?.write_all(mode.to_string().as_bytes())?;
+    Ok(())
+}
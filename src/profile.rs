@@ -29,31 +29,107 @@ impl Value {
     }
 }
 
+/// The profile every other profile implicitly extends. It does not need to
+/// exist.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The key of a profile which lists the profiles it extends.
+const EXTENDS_KEY: &str = "extends";
+
 pub struct Profile {
     base_path: PathBuf,
-    must_exist: bool,
 }
 
 impl Profile {
-    pub fn new<P: Into<PathBuf>>(base_path: P, must_exist: bool) -> Self {
+    pub fn new<P: Into<PathBuf>>(base_path: P) -> Self {
         Self {
             base_path: base_path.into(),
-            must_exist,
         }
     }
 
+    /// Loads the profile with the given name. The values of the profiles it
+    /// extends are deep-merged in order below its own values, on top of the
+    /// values of the default profile. Profiles which are extended multiple times
+    /// are only merged once, before all profiles extending them.
     pub fn load(&self, name: &str) -> Result<Value> {
+        let mut profiles = vec![];
+        if name != DEFAULT_PROFILE {
+            self.resolve(DEFAULT_PROFILE, &mut vec![], &mut profiles)?;
+        }
+        self.resolve(name, &mut vec![], &mut profiles)?;
+
+        Ok(profiles
+            .into_iter()
+            .fold(Value::None, |merged, (_, value)| merge(merged, value)))
+    }
+
+    /// Appends the profile with the given name to `profiles` after all profiles
+    /// it extends, unless it has been resolved already.
+    fn resolve(
+        &self,
+        name: &str,
+        stack: &mut Vec<String>,
+        profiles: &mut Vec<(String, Value)>,
+    ) -> Result<()> {
+        if stack.iter().any(|n| n == name) {
+            anyhow::bail!(
+                "Profile inheritance cycle: {} -> {name}",
+                stack.join(" -> ")
+            );
+        }
+        if profiles.iter().any(|(n, _)| n == name) {
+            return Ok(());
+        }
+
+        let mut value = match self.read(name)? {
+            Some(value) => value,
+            None if name == DEFAULT_PROFILE => return Ok(()),
+            None => match stack.last() {
+                Some(child) => anyhow::bail!(
+                    "Profile '{child}' extends '{name}', but no profile exists with name '{name}'"
+                ),
+                None => anyhow::bail!("No profile exists with name '{name}'"),
+            },
+        };
+
+        let parents = match value {
+            Value::Map(ref mut map) => match map.remove(EXTENDS_KEY) {
+                None => vec![],
+                Some(Value::String(parent)) => vec![parent],
+                Some(Value::List(parents)) => parents
+                    .into_iter()
+                    .map(|p| match p {
+                        Value::String(p) => Ok(p),
+                        _ => Err(anyhow::anyhow!(
+                            "'{EXTENDS_KEY}' of profile '{name}' must only contain profile names"
+                        )),
+                    })
+                    .collect::<Result<_>>()?,
+                Some(_) => anyhow::bail!(
+                    "'{EXTENDS_KEY}' of profile '{name}' must be a profile name or a list of names"
+                ),
+            },
+            _ => vec![],
+        };
+
+        stack.push(name.to_owned());
+        for parent in &parents {
+            self.resolve(parent, stack, profiles)?;
+        }
+        stack.pop();
+
+        profiles.push((name.to_owned(), value));
+        Ok(())
+    }
+
+    fn read(&self, name: &str) -> Result<Option<Value>> {
         let f = self
             .open_profile_file(&format!("{name}.yaml"))
             .transpose()
             .or_else(|| self.open_profile_file(&format!("{name}.yml")).transpose())
             .transpose()?;
 
-        match f {
-            Some(f) => Value::parse(f),
-            None if self.must_exist => Err(anyhow::anyhow!("No profile exists with name '{name}'")),
-            _ => Ok(Value::None),
-        }
+        f.map(Value::parse).transpose()
     }
 
     fn open_profile_file(&self, name: &str) -> Result<Option<File>> {
@@ -66,6 +142,26 @@ impl Profile {
     }
 }
 
+/// Deep-merges `over` into `base`. Maps are merged recursively while all other
+/// values of `over` replace the values of `base`. Missing values (`null`) do not
+/// replace anything.
+fn merge(base: Value, over: Value) -> Value {
+    match (base, over) {
+        (Value::Map(mut base), Value::Map(over)) => {
+            for (key, value) in over {
+                let merged = match base.remove(&key) {
+                    Some(b) => merge(b, value),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+            Value::Map(base)
+        }
+        (base, Value::None) => base,
+        (_, over) => over,
+    }
+}
+
 pub fn get_applied_profile<P: AsRef<Path>>(cache_dir: P) -> Result<Option<String>> {
     let p = cache_dir.as_ref().join(APPLIED_PROFILE_FILE);
     match read_file_to_string(p) {
@@ -90,3 +186,103 @@ fn read_file_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
     f.read_to_string(&mut buf)?;
     Ok(buf.trim().to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(yaml: &str) -> Value {
+        Value::parse(yaml.as_bytes()).unwrap()
+    }
+
+    fn json(v: &Value) -> serde_json::Value {
+        serde_json::to_value(v).unwrap()
+    }
+
+    fn profiles(files: &[(&str, &str)]) -> (tempfile::TempDir, Profile) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(PROFILE_DIR)).unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(PROFILE_DIR).join(name), content).unwrap();
+        }
+        let profile = Profile::new(dir.path());
+        (dir, profile)
+    }
+
+    #[test]
+    fn merge_deep() {
+        let merged = merge(
+            value("git: {name: alice, email: a@example.com}\nshell: bash"),
+            value("git: {email: alice@work.com}"),
+        );
+        assert_eq!(
+            json(&merged),
+            json(&value(
+                "git: {name: alice, email: alice@work.com}\nshell: bash"
+            ))
+        );
+    }
+
+    #[test]
+    fn merge_replaces_lists() {
+        let merged = merge(value("paths: [a, b]"), value("paths: [c]"));
+        assert_eq!(json(&merged), json(&value("paths: [c]")));
+    }
+
+    #[test]
+    fn merge_keeps_value_for_null() {
+        let merged = merge(value("name: alice"), value("name: null"));
+        assert_eq!(json(&merged), json(&value("name: alice")));
+    }
+
+    #[test]
+    fn load_in_order() {
+        let (_dir, profile) = profiles(&[
+            ("default.yaml", "name: default\nshell: bash\neditor: nano"),
+            ("base.yaml", "name: base\neditor: vim"),
+            ("work.yml", "extends: [base]\nname: work"),
+        ]);
+        assert_eq!(
+            json(&profile.load("work").unwrap()),
+            json(&value("name: work\nshell: bash\neditor: vim"))
+        );
+    }
+
+    #[test]
+    fn load_diamond() {
+        let (_dir, profile) = profiles(&[
+            ("common.yaml", "a: common\nb: common"),
+            ("left.yaml", "extends: common\na: left"),
+            ("right.yaml", "extends: common\nb: right"),
+            ("host.yaml", "extends: [left, right]"),
+        ]);
+        assert_eq!(
+            json(&profile.load("host").unwrap()),
+            json(&value("a: left\nb: right"))
+        );
+    }
+
+    #[test]
+    fn load_cycle() {
+        let (_dir, profile) = profiles(&[
+            ("a.yaml", "extends: b"),
+            ("b.yaml", "extends: c"),
+            ("c.yaml", "extends: a"),
+        ]);
+        let err = profile.load("a").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Profile inheritance cycle: a -> b -> c -> a"
+        );
+    }
+
+    #[test]
+    fn load_missing_parent() {
+        let (_dir, profile) = profiles(&[("work.yaml", "extends: base")]);
+        let err = profile.load("work").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Profile 'work' extends 'base', but no profile exists with name 'base'"
+        );
+    }
+}
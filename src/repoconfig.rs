@@ -0,0 +1,320 @@
+use anyhow::Result;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsStr,
+    fs::File,
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+
+pub const REPO_CONFIG_FILES: [&str; 2] = [".dotrs.yaml", ".dotrs.yml"];
+
+/// Repository-level configuration which is stored in the root of the stage.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RepoConfig {
+    /// When not empty, only files ending with one of these suffixes are rendered
+    /// as templates and the suffix is stripped from the target file name. All
+    /// other files are copied verbatim.
+    pub template_suffixes: Vec<String>,
+
+    /// File modes which are forced on copied files whose path relative to the
+    /// stage matches the given glob pattern. When multiple rules match, the
+    /// last one wins.
+    pub modes: Vec<ModeRule>,
+
+    /// Maps subdirectories of the stage to target directories other than the
+    /// home directory. Target directories may contain environment variables
+    /// like `$VAR`, `${VAR}` or `${VAR:-default}` and a leading `~`.
+    pub targets: BTreeMap<PathBuf, String>,
+}
+
+#[derive(Deserialize)]
+pub struct ModeRule {
+    pub path: String,
+    pub mode: FileMode,
+}
+
+/// A Unix file mode which is deserialized from an octal string like `"0600"`.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "String")]
+pub struct FileMode(pub u32);
+
+impl TryFrom<String> for FileMode {
+    type Error = std::num::ParseIntError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let digits = value.strip_prefix("0o").unwrap_or(&value);
+        u32::from_str_radix(digits, 8).map(|m| Self(m & 0o7777))
+    }
+}
+
+/// Resolves target paths for files in the stage and vice versa.
+pub struct TargetMap {
+    /// Stage subdirectories with their target directories, ordered from the
+    /// most to the least specific subdirectory.
+    roots: Vec<(PathBuf, PathBuf)>,
+}
+
+impl TargetMap {
+    /// Returns the target path for the given path relative to the stage.
+    pub fn target_for(&self, rel_path: &Path) -> PathBuf {
+        self.roots
+            .iter()
+            .find_map(|(prefix, root)| Some(root.join(rel_path.strip_prefix(prefix).ok()?)))
+            .expect("default target root")
+    }
+
+    /// Returns the path relative to the stage the given target path would be
+    /// applied from, or `None` if it is not located in any target directory.
+    pub fn source_for(&self, target: &Path) -> Option<PathBuf> {
+        self.roots
+            .iter()
+            .filter_map(|(prefix, root)| Some((root, prefix.join(target.strip_prefix(root).ok()?))))
+            .max_by_key(|(root, _)| root.components().count())
+            .map(|(_, rel_path)| rel_path)
+    }
+}
+
+pub struct ModeOverrides(Vec<(GlobMatcher, u32)>);
+
+impl ModeOverrides {
+    pub fn get(&self, path: &Path) -> Option<u32> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(matcher, _)| matcher.is_match(path))
+            .map(|(_, mode)| *mode)
+    }
+}
+
+impl RepoConfig {
+    pub fn load<P: AsRef<Path>>(stage_dir: P) -> Result<Self> {
+        for name in REPO_CONFIG_FILES {
+            match File::open(stage_dir.as_ref().join(name)) {
+                Ok(f) => return Ok(serde_yaml::from_reader(f)?),
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(Self::default())
+    }
+
+    pub fn mode_overrides(&self) -> Result<ModeOverrides> {
+        let overrides = self
+            .modes
+            .iter()
+            .map(|rule| {
+                let glob = GlobBuilder::new(&rule.path)
+                    .literal_separator(true)
+                    .build()?;
+                Ok((glob.compile_matcher(), rule.mode.0))
+            })
+            .collect::<Result<_>>()?;
+        Ok(ModeOverrides(overrides))
+    }
+
+    /// Resolves the configured target directories. Files in the stage which are
+    /// not located in any mapped subdirectory are applied to the home directory.
+    ///
+    /// When a target directory overriding the home directory is given, `~` is
+    /// expanded to it and all target directories are relocated into it.
+    pub fn target_map(&self, home_dir: &Path, target_dir: Option<&Path>) -> Result<TargetMap> {
+        let base_dir = target_dir.unwrap_or(home_dir);
+
+        let mut roots = self
+            .targets
+            .iter()
+            .map(|(prefix, target)| {
+                let root = expand_path(target, base_dir)?;
+                let root = match target_dir {
+                    Some(target_dir) => relocate(&root, home_dir, target_dir),
+                    None => root,
+                };
+                Ok((prefix.clone(), root))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        roots.push((PathBuf::new(), base_dir.to_owned()));
+        roots.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.components().count()));
+
+        Ok(TargetMap { roots })
+    }
+
+    /// Returns whether the file at the given target path should be rendered as
+    /// template and the path the file should be written to.
+    pub fn template_target(&self, target: &Path) -> (bool, PathBuf) {
+        if self.template_suffixes.is_empty() {
+            return (true, target.to_owned());
+        }
+
+        let stripped = target.file_name().and_then(OsStr::to_str).and_then(|name| {
+            self.template_suffixes
+                .iter()
+                .find_map(|suffix| name.strip_suffix(suffix.as_str()))
+                .filter(|name| !name.is_empty())
+        });
+
+        match stripped {
+            Some(name) => (true, target.with_file_name(name)),
+            None => (false, target.to_owned()),
+        }
+    }
+}
+
+/// Moves the given path into the target directory. Paths in the home directory
+/// keep their location relative to it.
+fn relocate(path: &Path, home_dir: &Path, target_dir: &Path) -> PathBuf {
+    if path.starts_with(target_dir) {
+        return path.to_owned();
+    }
+
+    match path.strip_prefix(home_dir) {
+        Ok(rel_path) => target_dir.join(rel_path),
+        Err(_) => target_dir.join(
+            path.components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .collect::<PathBuf>(),
+        ),
+    }
+}
+
+/// Expands environment variables (`$VAR`, `${VAR}` and `${VAR:-default}`) and a
+/// leading `~` in the given path.
+fn expand_path(s: &str, home_dir: &Path) -> Result<PathBuf> {
+    let expanded = expand_vars(s)?;
+    match expanded.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            Ok(home_dir.join(rest.trim_start_matches('/')))
+        }
+        _ => Ok(PathBuf::from(expanded)),
+    }
+}
+
+fn expand_vars(s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        let (name, default) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = closing_brace(braced)
+                .ok_or_else(|| anyhow::anyhow!("unclosed variable in '{s}'"))?;
+            rest = &braced[end + 1..];
+            match braced[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&braced[..end], None),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+            rest = &rest[end..];
+            // A `$` which is not followed by a name is kept as it is.
+            if name.is_empty() {
+                out.push('$');
+                continue;
+            }
+            (name, None)
+        };
+
+        match (env::var(name).ok().filter(|v| !v.is_empty()), default) {
+            (Some(value), _) => out.push_str(&value),
+            (None, Some(default)) => out.push_str(&expand_vars(default)?),
+            (None, None) => anyhow::bail!("variable '{name}' used in '{s}' is not set"),
+        }
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Returns the index of the `}` closing a variable, skipping over nested
+/// variables in its default value.
+fn closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_plain_and_braced() {
+        env::set_var("DOTRS_TEST_PLAIN", "value");
+        assert_eq!(expand_vars("a/$DOTRS_TEST_PLAIN/b").unwrap(), "a/value/b");
+        assert_eq!(expand_vars("${DOTRS_TEST_PLAIN}x").unwrap(), "valuex");
+    }
+
+    #[test]
+    fn expand_defaults() {
+        env::set_var("DOTRS_TEST_DEFAULT", "set");
+        assert_eq!(expand_vars("${DOTRS_TEST_DEFAULT:-other}").unwrap(), "set");
+        assert_eq!(
+            expand_vars("${DOTRS_TEST_UNSET:-other}/x").unwrap(),
+            "other/x"
+        );
+        assert_eq!(
+            expand_vars("${DOTRS_TEST_UNSET:-${DOTRS_TEST_DEFAULT}/y}").unwrap(),
+            "set/y"
+        );
+        assert_eq!(
+            expand_vars("${DOTRS_TEST_UNSET:-${DOTRS_TEST_UNSET2:-z}}/x").unwrap(),
+            "z/x"
+        );
+    }
+
+    #[test]
+    fn expand_unset_fails() {
+        let err = expand_vars("$DOTRS_TEST_MISSING/x").unwrap_err();
+        assert!(err.to_string().contains("'DOTRS_TEST_MISSING'"));
+        assert!(expand_vars("${DOTRS_TEST_MISSING").is_err());
+    }
+
+    #[test]
+    fn expand_lone_dollar() {
+        assert_eq!(expand_vars("a$").unwrap(), "a$");
+        assert_eq!(expand_vars("a$/b").unwrap(), "a$/b");
+    }
+
+    #[test]
+    fn expand_tilde() {
+        let home = Path::new("/home/alice");
+        assert_eq!(expand_path("~", home).unwrap(), home);
+        assert_eq!(expand_path("~/x", home).unwrap(), home.join("x"));
+        assert_eq!(expand_path("~bob/x", home).unwrap(), Path::new("~bob/x"));
+    }
+
+    #[test]
+    fn relocate_paths() {
+        let home = Path::new("/home/alice");
+        let target = Path::new("/mnt/root");
+        assert_eq!(
+            relocate(Path::new("/home/alice/.config"), home, target),
+            Path::new("/mnt/root/.config")
+        );
+        assert_eq!(
+            relocate(Path::new("/opt/tools/etc"), home, target),
+            Path::new("/mnt/root/opt/tools/etc")
+        );
+        assert_eq!(
+            relocate(Path::new("/mnt/root/x"), home, target),
+            Path::new("/mnt/root/x")
+        );
+    }
+}
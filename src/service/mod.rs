@@ -1,5 +1,7 @@
+use crate::config::Config;
+use crate::util::dotfiles::{self, ApplyOptions, ConflictPolicy};
 use crate::util::git::DEFAULT_COMMIT_AUTHOR;
-use crate::{config::Config, util::dotfiles};
+use crate::util::lock::Lock;
 use anyhow::Result;
 use debounce::EventDebouncer;
 use log::{debug, error, info};
@@ -20,6 +22,7 @@ enum Event {
 
 pub struct Service {
     cfg: Config,
+    apply_opts: ApplyOptions,
     dir: PathBuf,
     rx: Receiver<Event>,
 
@@ -85,22 +88,57 @@ impl Service {
             thread::sleep(pull_frequency);
         });
 
+        // Locally modified files are never overwritten by the service
+        // and are only logged instead. A single broken file should not
+        // prevent all other files from being updated.
+        let apply_opts = ApplyOptions {
+            conflict: ConflictPolicy::Skip,
+            keep_going: true,
+            ..Default::default()
+        };
+
         Ok(Service {
             cfg,
+            apply_opts,
             dir,
             rx,
             _watcher: Box::new(watcher),
         })
     }
 
+    fn apply(&self) -> Result<()> {
+        let report = dotfiles::apply(&self.cfg, &self.apply_opts)?;
+        info!(
+            "applied dotfiles: {} written, {} unchanged, {} deleted",
+            report.written, report.unchanged, report.deleted
+        );
+        for f in &report.failures {
+            error!(
+                "failed applying {}: {:#}",
+                f.path.to_string_lossy(),
+                f.error
+            );
+        }
+        Ok(())
+    }
+
     pub fn watch(&self) -> Result<()> {
         info!("Watching {} ...", self.dir.to_string_lossy());
 
         for event in &self.rx {
             info!("received event: {event:?}");
+            // Operations started by hand take precedence, so the service
+            // waits for them instead of dropping the event.
+            let _lock = match Lock::acquire(&self.cfg.cache_dir, true) {
+                Ok(lock) => lock,
+                Err(err) => {
+                    error!("failed acquiring lock: {err}");
+                    continue;
+                }
+            };
             match event {
                 Event::Apply => {
-                    if let Err(err) = dotfiles::apply(&self.cfg, None::<&str>) {
+                    if let Err(err) = self.apply() {
                         error!("failed applying dotfiles: {err}");
                     }
                 }
@@ -114,7 +152,7 @@ impl Service {
                 Event::Pull => {
                     if let Err(err) = dotfiles::pull(&self.cfg) {
                         error!("failed pulling dotfiles stage: {err}");
-                    } else if let Err(err) = dotfiles::apply(&self.cfg, None::<&str>) {
+                    } else if let Err(err) = self.apply() {
                         error!("failed applying dotfiles after pull: {err}");
                     }
                 }
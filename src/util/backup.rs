@@ -0,0 +1,155 @@
+use super::dotfiles::link;
+use anyhow::Result;
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Component, Path, PathBuf, MAIN_SEPARATOR_STR},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const BACKUP_DIR: &str = "backups";
+
+/// The name of the backup which holds the files which existed before dotrs
+/// applied a file to their path for the first time.
+pub const ORIGINALS: &str = "originals";
+
+/// A backup location in the cache directory which collects copies of files
+/// before they are overwritten.
+pub struct Backup {
+    dir: PathBuf,
+}
+
+impl Backup {
+    /// Creates a new backup named after the current time in milliseconds. The
+    /// directory is created exclusively, so that backups created at the same
+    /// time do not share their directory.
+    pub fn new<P: AsRef<Path>>(cache_dir: P) -> Result<Self> {
+        let dir = cache_dir.as_ref().join(BACKUP_DIR);
+        fs::create_dir_all(&dir)?;
+
+        let mut id = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        loop {
+            match fs::create_dir(dir.join(id.to_string())) {
+                Ok(()) => return Ok(Self::open(cache_dir, &id.to_string())),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => id += 1,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Returns the backup of files which existed before they have been
+    /// overwritten by dotrs for the first time.
+    pub fn originals<P: AsRef<Path>>(cache_dir: P) -> Self {
+        Self::open(cache_dir, ORIGINALS)
+    }
+
+    pub fn open<P: AsRef<Path>>(cache_dir: P, id: &str) -> Self {
+        Self {
+            dir: cache_dir.as_ref().join(BACKUP_DIR).join(id),
+        }
+    }
+
+    /// Returns all backups in the cache directory, newest first. The backup of
+    /// original files comes last.
+    pub fn all<P: AsRef<Path>>(cache_dir: P) -> Result<Vec<Self>> {
+        let dir = cache_dir.as_ref().join(BACKUP_DIR);
+        let entries = match dir.read_dir() {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut backups = vec![];
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                backups.push(Self { dir: entry.path() });
+            }
+        }
+
+        backups.sort_by_key(|b| std::cmp::Reverse(b.id().parse::<u128>().unwrap_or_default()));
+        Ok(backups)
+    }
+
+    pub fn id(&self) -> String {
+        self.dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Returns the original paths of all files in the backup.
+    pub fn files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        let mut dirs = vec![self.dir.clone()];
+
+        while let Some(dir) = dirs.pop() {
+            for entry in dir.read_dir()? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    dirs.push(entry.path());
+                } else {
+                    let relative = entry.path().strip_prefix(&self.dir)?.to_owned();
+                    files.push(Path::new(MAIN_SEPARATOR_STR).join(relative));
+                }
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+
+    /// Returns whether the backup contains a copy of the given file.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.path_of(path).symlink_metadata().is_ok()
+    }
+
+    /// Copies the given file into the backup location and returns the path of
+    /// the copy. Symlinks are copied as links.
+    pub fn save(&self, path: &Path) -> Result<PathBuf> {
+        let backup_path = self.path_of(path);
+
+        if let Some(parent) = backup_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match path.symlink_metadata()?.is_symlink() {
+            true => link(&fs::read_link(path)?, &backup_path)?,
+            false => _ = fs::copy(path, &backup_path)?,
+        }
+
+        Ok(backup_path)
+    }
+
+    /// Copies the backed up version of the given file back to its location.
+    pub fn restore(&self, path: &Path) -> Result<()> {
+        let backup_path = self.path_of(path);
+        let Ok(meta) = backup_path.symlink_metadata() else {
+            anyhow::bail!("{path:?} is not contained in backup {}", self.id());
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if meta.is_symlink() {
+            link(&fs::read_link(&backup_path)?, path)?;
+            return Ok(());
+        }
+        // Do not write through links which point into the stage.
+        if path.symlink_metadata().is_ok_and(|m| m.is_symlink()) {
+            fs::remove_file(path)?;
+        }
+        fs::copy(backup_path, path)?;
+
+        Ok(())
+    }
+
+    /// Returns the location of the copy of the given file in the backup.
+    pub fn path_of(&self, path: &Path) -> PathBuf {
+        let relative: PathBuf = path
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        self.dir.join(relative)
+    }
+}
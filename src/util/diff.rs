@@ -0,0 +1,113 @@
+use similar::{ChangeTag, DiffTag, TextDiff};
+use std::fmt::Write;
+use yansi::Paint;
+
+/// Creates a colorized unified diff between `old` and `new`.
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let diff = TextDiff::from_lines(old, new);
+    let mut out = String::new();
+
+    _ = writeln!(out, "{}", format!("--- {old_name}").bold());
+    _ = writeln!(out, "{}", format!("+++ {new_name}").bold());
+
+    for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+        _ = writeln!(out, "{}", hunk.header().cyan());
+        for change in hunk.iter_changes() {
+            let value = change.value();
+            let value = value.strip_suffix('\n').unwrap_or(value);
+            let line = match change.tag() {
+                ChangeTag::Delete => format!("-{value}").red().to_string(),
+                ChangeTag::Insert => format!("+{value}").green().to_string(),
+                ChangeTag::Equal => format!(" {value}"),
+            };
+            _ = writeln!(out, "{line}");
+            if change.missing_newline() {
+                _ = writeln!(out, "{}", "\\ No newline at end of file".dim());
+            }
+        }
+    }
+
+    out
+}
+
+/// Maps changes made to the rendered output of a template back onto the
+/// template itself. This only succeeds when every line of the template maps to
+/// exactly one line of the rendered output and all changed lines are plain
+/// text without any template expressions.
+pub fn patch_template(template: &str, rendered: &str, modified: &str) -> Option<String> {
+    let template_lines: Vec<_> = template.split_inclusive('\n').collect();
+    let rendered_lines: Vec<_> = rendered.split_inclusive('\n').collect();
+    let modified_lines: Vec<_> = modified.split_inclusive('\n').collect();
+
+    if template_lines.len() != rendered_lines.len() {
+        return None;
+    }
+
+    let is_literal = |i: usize| template_lines[i] == rendered_lines[i];
+    let is_expression = |i: usize| template_lines[i].contains("{{");
+
+    if (0..template_lines.len()).any(|i| !is_literal(i) && !is_expression(i)) {
+        return None;
+    }
+
+    let mut patched = String::with_capacity(modified.len());
+
+    for op in TextDiff::from_lines(rendered, modified).ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        match tag {
+            DiffTag::Equal => patched.extend(template_lines[old_range].iter().copied()),
+            DiffTag::Insert => patched.extend(modified_lines[new_range].iter().copied()),
+            DiffTag::Delete | DiffTag::Replace => {
+                if !old_range
+                    .clone()
+                    .all(|i| is_literal(i) && !is_expression(i))
+                {
+                    return None;
+                }
+                patched.extend(modified_lines[new_range].iter().copied());
+            }
+        }
+    }
+
+    Some(patched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::patch_template;
+
+    const TEMPLATE: &str = "[user]\nname = {{name}}\nshell = bash\n";
+    const RENDERED: &str = "[user]\nname = alice\nshell = bash\n";
+
+    #[test]
+    fn patch_literal_line() {
+        let modified = "[user]\nname = alice\nshell = zsh\n";
+        assert_eq!(
+            patch_template(TEMPLATE, RENDERED, modified).as_deref(),
+            Some("[user]\nname = {{name}}\nshell = zsh\n")
+        );
+    }
+
+    #[test]
+    fn refuse_expression_line() {
+        let modified = "[user]\nname = bob\nshell = bash\n";
+        assert_eq!(patch_template(TEMPLATE, RENDERED, modified), None);
+    }
+
+    #[test]
+    fn refuse_line_count_mismatch() {
+        let template = "a\n{{#if work}}\nb\n{{/if}}\nc\n";
+        let rendered = "a\nb\nc\n";
+        let modified = "a\nb\nd\n";
+        assert_eq!(patch_template(template, rendered, modified), None);
+    }
+
+    #[test]
+    fn insert_at_eof() {
+        let modified = "[user]\nname = alice\nshell = bash\neditor = vim\n";
+        assert_eq!(
+            patch_template(TEMPLATE, RENDERED, modified).as_deref(),
+            Some("[user]\nname = {{name}}\nshell = bash\neditor = vim\n")
+        );
+    }
+}
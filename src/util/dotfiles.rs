@@ -1,51 +1,1104 @@
+use super::backup::Backup;
+use super::diff;
 use super::git::{self, Change, Git};
 use crate::config::Config;
-use crate::filecache::{FileCache, NAME_FILECACHE};
-use crate::profile::{get_applied_profile, write_applied_profile, Profile, Value};
-use anyhow::Result;
-use handlebars::Handlebars;
+use crate::facts::{Facts, FACTS_KEY};
+use crate::filecache::{self, Entry, EntryKind, FileCache, NAME_FILECACHE};
+use crate::mode::{get_applied_mode, write_applied_mode, DeployMode};
+use crate::profile::{get_applied_profile, write_applied_profile, Profile, Value, DEFAULT_PROFILE};
+use crate::repoconfig::{ModeOverrides, RepoConfig, TargetMap, REPO_CONFIG_FILES};
+use anyhow::{Context as _, Result};
+use clap::ValueEnum;
+use handlebars::{Context, Handlebars, RenderContext, Renderable, StringOutput, Template};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use ignore::{DirEntry, WalkBuilder};
-use log::debug;
-use std::fs::{self, File};
-use std::io::{Read, Write};
-use std::path::{Path, PathBuf};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::{self, Path, PathBuf};
 
-pub fn apply<S: Into<String>>(cfg: &Config, profile: Option<S>) -> Result<()> {
+/// Defines how tracked files are handled which have been modified in the home
+/// directory since they have been applied.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ConflictPolicy {
+    /// Refuse to apply any changes
+    #[default]
+    Abort,
+    /// Back up the modified files before overwriting them
+    Backup,
+    /// Overwrite the modified files
+    Overwrite,
+    /// Leave the modified files untouched
+    Skip,
+}
+
+#[derive(Default)]
+pub struct ApplyOptions {
+    /// The profile to apply; falls back to the previously applied profile.
+    pub profile: Option<String>,
+    /// The deploy mode to use; falls back to the previously used mode.
+    pub mode: Option<DeployMode>,
+    /// How to handle tracked files which have been modified locally.
+    pub conflict: ConflictPolicy,
+    /// Apply all files which can be applied instead of failing on the first
+    /// error. Failed files are reported instead and changes are not rolled back.
+    pub keep_going: bool,
+}
+
+/// A file which could not be rendered or written to its target.
+pub struct Failure {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+/// The outcome of a successful apply.
+#[derive(Default)]
+pub struct ApplyReport {
+    /// The number of files which have been created, modified or changed mode.
+    pub written: usize,
+    /// The number of files which have been left untouched as they are up to date.
+    pub unchanged: usize,
+    /// The number of removed files which have been deleted.
+    pub deleted: usize,
+    /// Files which failed to apply when keep going is enabled.
+    pub failures: Vec<Failure>,
+}
+
+/// A file rendered from the stage which is ready to be written to its target.
+pub struct RenderedFile {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub content: Vec<u8>,
+    pub templated: bool,
+    pub kind: EntryKind,
+    pub mode: Option<u32>,
+}
+
+impl RenderedFile {
+    fn entry(&self, profile: Option<&str>) -> Entry {
+        Entry {
+            path: self.target.clone(),
+            kind: self.kind,
+            mode: match self.kind {
+                EntryKind::File => self.mode,
+                EntryKind::Symlink => None,
+            },
+            hash: match self.kind {
+                EntryKind::File => Some(filecache::hash(&self.content)),
+                EntryKind::Symlink => None,
+            },
+            source: Some(self.source.clone()),
+            templated: self.templated,
+            profile: profile.map(str::to_owned),
+            applied_at: Some(filecache::timestamp()),
+            backup: None,
+        }
+    }
+
+    /// Compares the rendered file with the current state of its target.
+    pub fn action(&self) -> Result<Action<'_>> {
+        let up_to_date = match self.kind {
+            EntryKind::Symlink => is_link_to(&self.target, &self.source),
+            EntryKind::File => !is_symlink(&self.target),
+        };
+
+        let action = match fs::read(&self.target) {
+            Ok(current) if up_to_date && current == self.content => {
+                let current_mode = file_mode(&fs::metadata(&self.target)?);
+                match self.kind == EntryKind::File && current_mode != self.mode {
+                    true => Action::Chmod(self, current_mode),
+                    false => Action::Unchanged(self),
+                }
+            }
+            Ok(current) => Action::Modify(self, current),
+            Err(err) if err.kind() == ErrorKind::NotFound => Action::Create(self),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(action)
+    }
+}
+
+/// The set of changes which applying the stage would make to the home directory.
+pub struct Plan {
+    pub profile: Option<String>,
+    pub mode: DeployMode,
+    pub files: Vec<RenderedFile>,
+    pub removed: Vec<Entry>,
+    /// Tracked targets which have been modified since they have been applied
+    /// and which would be overwritten or deleted.
+    pub conflicts: Vec<PathBuf>,
+    /// Files which failed to render when keep going is enabled.
+    pub failures: Vec<Failure>,
+}
+
+pub enum Action<'a> {
+    Create(&'a RenderedFile),
+    Modify(&'a RenderedFile, Vec<u8>),
+    Chmod(&'a RenderedFile, Option<u32>),
+    Unchanged(&'a RenderedFile),
+    Delete(&'a Path),
+}
+
+impl Plan {
+    /// Compares the rendered files with the current state of their targets.
+    pub fn actions(&self) -> Result<Vec<Action<'_>>> {
+        let mut actions = Vec::with_capacity(self.files.len() + self.removed.len());
+
+        for f in &self.files {
+            actions.push(f.action()?);
+        }
+
+        actions.extend(self.removed.iter().map(|e| Action::Delete(&e.path)));
+
+        Ok(actions)
+    }
+}
+
+/// Renders all dotfiles from stage in memory without touching the home directory
+/// or the file cache.
+pub fn plan(cfg: &Config, opts: &ApplyOptions) -> Result<Plan> {
     assert_stage_dir_initialized(cfg)?;
 
-    let home_dir = super::home_dir()?;
+    let state_dir = cfg.state_dir()?;
 
-    debug!("home_dir = {home_dir:?}");
+    debug!("target_dir = {:?}", cfg.target_dir()?);
 
-    let profile = match profile {
-        Some(p) => Some(p.into()),
-        None => get_applied_profile(&cfg.cache_dir)?,
+    let profile = match opts.profile {
+        Some(ref p) => Some(p.clone()),
+        None => get_applied_profile(&state_dir)?,
     };
 
     debug!("profile = {profile:?}");
 
-    let copied_files = apply_recursively(&cfg.stage_dir, &home_dir, profile.as_deref())?;
+    let mode = match opts.mode {
+        Some(mode) => mode,
+        None => get_applied_mode(&state_dir)?.unwrap_or_default(),
+    };
+
+    debug!("mode = {mode}");
+
+    let renderer = Renderer::new(cfg, profile.as_deref())?;
+    let mut files = vec![];
+    let mut failures = vec![];
+    for path in renderer.stage_files()? {
+        match renderer.render(&path) {
+            Ok(f) => files.push(f),
+            Err(error) if opts.keep_going => failures.push(Failure {
+                path: renderer.target_for(&path)?.1,
+                error,
+            }),
+            Err(err) => return Err(err),
+        }
+    }
+
+    // Links carry the mode of the file in the stage, so files with a forced
+    // mode are copied even in symlink mode.
+    if mode == DeployMode::Symlink {
+        for f in files.iter_mut() {
+            if !f.templated && !renderer.has_mode_override(&f.source)? {
+                f.kind = EntryKind::Symlink;
+            }
+        }
+    }
+
+    let fc = FileCache::open(state_dir.join(NAME_FILECACHE))?;
+    let entries: Vec<_> = files.iter().map(|f| f.entry(profile.as_deref())).collect();
+    let failed: HashSet<_> = failures.iter().map(|f| &f.path).collect();
+    let removed = fc
+        .diff(&entries)
+        .removed
+        .into_iter()
+        .filter(|e| !failed.contains(&e.path))
+        .cloned()
+        .collect();
+
+    let by_target: HashMap<_, _> = files.iter().map(|f| (&f.target, f)).collect();
+    let mut conflicts = vec![];
+    for e in fc.get() {
+        if !locally_modified(e).with_context(|| format!("failed checking {:?}", e.path))? {
+            continue;
+        }
+        let rendered = by_target.get(&e.path);
+        if rendered.is_some_and(|f| fs::read(&f.target).is_ok_and(|c| c == f.content)) {
+            continue;
+        }
+        conflicts.push(e.path.clone());
+    }
+
+    Ok(Plan {
+        profile,
+        mode,
+        files,
+        removed,
+        conflicts,
+        failures,
+    })
+}
+
+pub fn apply(cfg: &Config, opts: &ApplyOptions) -> Result<ApplyReport> {
+    let plan = plan(cfg, opts)?;
+
+    let mut skipped = HashSet::new();
 
-    let mut fc = FileCache::open(cfg.cache_dir.as_ref().join(NAME_FILECACHE))?;
+    if !plan.conflicts.is_empty() {
+        match opts.conflict {
+            ConflictPolicy::Abort => anyhow::bail!(
+                "the following files have been modified locally since they have been applied:\n{}\n\
+                use --conflict to choose how to handle these files",
+                plan.conflicts
+                    .iter()
+                    .map(|p| format!("  - {}", p.to_string_lossy()))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+            ConflictPolicy::Backup => {
+                let backup = Backup::new(cfg.state_dir()?)?;
+                for p in &plan.conflicts {
+                    let backup_path = backup.save(p)?;
+                    warn!("backed up locally modified file {p:?} to {backup_path:?}");
+                }
+            }
+            ConflictPolicy::Overwrite => {
+                for p in &plan.conflicts {
+                    warn!("overwriting locally modified file {p:?}");
+                }
+            }
+            ConflictPolicy::Skip => {
+                for p in &plan.conflicts {
+                    warn!("skipping locally modified file {p:?}");
+                }
+                skipped.extend(plan.conflicts.iter().map(PathBuf::as_path));
+            }
+        }
+    }
+
+    let state_dir = cfg.state_dir()?;
+    let mut fc = FileCache::open(state_dir.join(NAME_FILECACHE))?;
+
+    // Files which existed before they are applied for the first time are
+    // backed up, so that they can be restored when they are removed again. An
+    // existing copy is kept, as it has been taken before dotrs touched the file.
+    let tracked: HashSet<_> = fc.get().iter().map(|e| &e.path).collect();
+    let originals = Backup::originals(&state_dir);
+    let mut backups = HashMap::new();
+    let mut saved_originals = vec![];
+    for f in plan
+        .files
+        .iter()
+        .filter(|f| !skipped.contains(f.target.as_path()))
+    {
+        let existing = f.target.symlink_metadata();
+        if tracked.contains(&f.target) || !existing.is_ok_and(|m| m.is_file() || m.is_symlink()) {
+            continue;
+        }
+        if originals.contains(&f.target) {
+            backups.insert(&f.target, originals.path_of(&f.target));
+        } else {
+            let backup_path = originals.save(&f.target)?;
+            info!("backed up existing file {:?} to {backup_path:?}", f.target);
+            saved_originals.push(backup_path.clone());
+            backups.insert(&f.target, backup_path);
+        }
+    }
 
-    let diff = fc.diff(&copied_files);
-    for f in diff.removed {
-        debug!("delete {f:?}");
-        fs::remove_file(f)?;
+    let mut tx = Transaction::default();
+    let mut report = ApplyReport::default();
+    if let Err(err) = write_plan(&plan, &skipped, &mut tx, opts, &mut report) {
+        warn!("applying dotfiles failed, rolling back changes ...");
+        tx.rollback();
+        for backup_path in saved_originals {
+            if let Err(err) = fs::remove_file(&backup_path) {
+                warn!("failed to remove backup {backup_path:?}: {err}");
+            }
+        }
+        return Err(err);
     }
+    report.failures.extend(plan.failures);
 
-    fc.set(copied_files);
+    // Failed and skipped targets keep their previous cache entries, so that
+    // the cache reflects what has actually been written. Removed targets which
+    // do not exist anymore are dropped even when their removal failed.
+    let removed: HashSet<_> = plan.removed.iter().map(|e| e.path.as_path()).collect();
+    let failed: HashSet<_> = report.failures.iter().map(|f| f.path.as_path()).collect();
+    let kept = |p: &Path| {
+        (skipped.contains(p) || failed.contains(p))
+            && !(removed.contains(p) && p.symlink_metadata().is_err())
+    };
+    let previous: HashMap<_, _> = fc.get().iter().map(|e| (&e.path, e)).collect();
 
+    let entries = plan
+        .files
+        .iter()
+        .map(|f| {
+            let mut e = f.entry(plan.profile.as_deref());
+            e.backup = match backups.remove(&f.target) {
+                Some(backup_path) => Some(backup_path),
+                None => previous.get(&e.path).and_then(|o| o.backup.clone()),
+            };
+            e
+        })
+        .filter(|e| !kept(&e.path))
+        .chain(fc.get().iter().filter(|e| kept(&e.path)).cloned())
+        .collect();
+    fc.set(entries);
+    fc.add_dirs(tx.created_dirs);
+    prune_dirs(&mut fc);
     fc.store()?;
 
-    if let Some(profile) = profile {
+    for e in plan.removed.iter().filter(|e| !kept(&e.path)) {
+        discard_original(e);
+    }
+
+    if let Some(profile) = plan.profile {
         debug!("writing profile {profile} to cache ...");
-        write_applied_profile(&cfg.cache_dir, &profile)?;
+        write_applied_profile(&state_dir, &profile)?;
+    }
+
+    write_applied_mode(&state_dir, plan.mode)?;
+
+    Ok(report)
+}
+
+/// Writes all files of the plan which are not up to date and removes the
+/// removed entries. When keeping going, failed files are recorded in the report
+/// instead of failing the whole operation.
+fn write_plan(
+    plan: &Plan,
+    skipped: &HashSet<&Path>,
+    tx: &mut Transaction,
+    opts: &ApplyOptions,
+    report: &mut ApplyReport,
+) -> Result<()> {
+    let keep_going = opts.keep_going;
+    // Conflicts which are overwritten are removed even when the user replaced
+    // a link with a regular file.
+    let force = matches!(
+        opts.conflict,
+        ConflictPolicy::Backup | ConflictPolicy::Overwrite
+    );
+
+    let conflicts: HashSet<_> = plan.conflicts.iter().collect();
+
+    for f in plan
+        .files
+        .iter()
+        .filter(|f| !skipped.contains(f.target.as_path()))
+    {
+        match write_file(f, tx) {
+            Ok(true) => report.written += 1,
+            Ok(false) => report.unchanged += 1,
+            Err(error) if keep_going => report.failures.push(Failure {
+                path: f.target.clone(),
+                error,
+            }),
+            Err(err) => return Err(err.context(format!("failed applying {:?}", f.target))),
+        }
+    }
+
+    for e in plan
+        .removed
+        .iter()
+        .filter(|e| !skipped.contains(e.path.as_path()))
+    {
+        debug!("delete {:?}", e.path);
+        let force = force && conflicts.contains(&e.path);
+        match tx.save(&e.path).and_then(|_| remove_entry(e, force)) {
+            Ok(()) => report.deleted += 1,
+            Err(error) if keep_going => report.failures.push(Failure {
+                path: e.path.clone(),
+                error: error.into(),
+            }),
+            Err(err) => return Err(err).with_context(|| format!("failed removing {:?}", e.path)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a single file to its target unless it is already up to date. Returns
+/// whether the target has been changed.
+fn write_file(f: &RenderedFile, tx: &mut Transaction) -> Result<bool> {
+    match f.action()? {
+        Action::Unchanged(_) => {
+            debug!("unchanged {:?}", f.target);
+            return Ok(false);
+        }
+        Action::Chmod(_, _) => {
+            if let Some(mode) = f.mode {
+                tx.save(&f.target)?;
+                set_file_mode(&f.target, mode)?;
+                debug!("changed mode of {:?} to {mode:o}", f.target);
+            }
+            return Ok(true);
+        }
+        _ => {}
+    }
+
+    if let Some(parent) = f.target.parent() {
+        tx.create_dir_all(parent)?;
+    }
+
+    match f.kind {
+        EntryKind::File => {
+            tx.save(&f.target)?;
+            write_atomic(&f.target, &f.content, f.mode)?;
+            debug!("copied {:?} -> {:?}", f.source, f.target);
+        }
+        EntryKind::Symlink => {
+            tx.save(&f.target)?;
+            link(&f.source, &f.target)?;
+            debug!("linked {:?} -> {:?}", f.target, f.source);
+        }
+    }
+
+    Ok(true)
+}
+
+/// The state of a path before it has been modified by an apply.
+enum Saved {
+    Missing,
+    File(Vec<u8>, Option<u32>),
+    Symlink(PathBuf),
+}
+
+/// Records the previous state of all paths modified during an apply, so that
+/// they can be restored when the apply fails partway.
+#[derive(Default)]
+struct Transaction {
+    saved: Vec<(PathBuf, Saved)>,
+    saved_paths: HashSet<PathBuf>,
+    created_dirs: Vec<PathBuf>,
+}
+
+impl Transaction {
+    fn save(&mut self, p: &Path) -> io::Result<()> {
+        if self.saved_paths.contains(p) {
+            return Ok(());
+        }
+
+        let saved = match p.symlink_metadata() {
+            Ok(meta) if meta.is_symlink() => Saved::Symlink(fs::read_link(p)?),
+            Ok(meta) if meta.is_file() => Saved::File(fs::read(p)?, file_mode(&meta)),
+            Ok(_) => {
+                return Err(io::Error::other(format!(
+                    "{} is not a file",
+                    p.to_string_lossy()
+                )))
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Saved::Missing,
+            Err(err) => return Err(err),
+        };
+
+        self.saved_paths.insert(p.to_path_buf());
+        self.saved.push((p.to_path_buf(), saved));
+        Ok(())
+    }
+
+    fn create_dir_all(&mut self, dir: &Path) -> io::Result<()> {
+        let missing: Vec<_> = dir
+            .ancestors()
+            .take_while(|d| !d.as_os_str().is_empty() && !d.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        fs::create_dir_all(dir)?;
+        self.created_dirs.extend(missing.into_iter().rev());
+        Ok(())
+    }
+
+    /// Restores all saved paths in reverse order. Failures are logged, so that
+    /// as many paths as possible are restored.
+    fn rollback(self) {
+        for (p, saved) in self.saved.iter().rev() {
+            let res = match saved {
+                Saved::Missing => match p.symlink_metadata() {
+                    Ok(_) => fs::remove_file(p),
+                    Err(_) => Ok(()),
+                },
+                Saved::File(content, mode) => write_atomic(p, content, *mode),
+                Saved::Symlink(original) => link(original, p),
+            };
+            match res {
+                Ok(()) => debug!("restored {p:?}"),
+                Err(err) => warn!("failed to restore {p:?}: {err}"),
+            }
+        }
+
+        for dir in self.created_dirs.iter().rev() {
+            if let Err(err) = fs::remove_dir(dir) {
+                warn!("failed to remove created directory {dir:?}: {err}");
+            }
+        }
+    }
+}
+
+/// The state of a dotfile in the target directory compared to the stage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileState {
+    /// The target is up to date with the stage.
+    InSync,
+    /// The target has been modified since it has been applied.
+    Modified,
+    /// The target has been applied but does not exist anymore.
+    Missing,
+    /// The stage contains changes which have not been applied yet.
+    Pending,
+    /// The file in the stage could not be rendered.
+    Failed,
+}
+
+/// Compares the applied dotfiles with the stage and returns the state of each
+/// target.
+pub fn file_states(cfg: &Config) -> Result<Vec<(PathBuf, FileState)>> {
+    let plan = plan(
+        cfg,
+        &ApplyOptions {
+            keep_going: true,
+            ..Default::default()
+        },
+    )?;
+
+    let fc = FileCache::open(cfg.state_dir()?.join(NAME_FILECACHE))?;
+    let tracked: HashSet<_> = fc.get().iter().map(|e| &e.path).collect();
+    let conflicts: HashSet<_> = plan.conflicts.iter().map(PathBuf::as_path).collect();
+
+    let mut states = vec![];
+    for action in plan.actions()? {
+        let (path, state) = match action {
+            Action::Unchanged(f) => (f.target.as_path(), FileState::InSync),
+            Action::Create(f) if tracked.contains(&f.target) => {
+                (f.target.as_path(), FileState::Missing)
+            }
+            Action::Create(f) => (f.target.as_path(), FileState::Pending),
+            Action::Modify(f, _) | Action::Chmod(f, _) => (f.target.as_path(), FileState::Pending),
+            Action::Delete(p) => (p, FileState::Pending),
+        };
+        let state = match conflicts.contains(path) {
+            true => FileState::Modified,
+            false => state,
+        };
+        states.push((path.to_owned(), state));
+    }
+
+    states.extend(
+        plan.failures
+            .into_iter()
+            .map(|f| (f.path, FileState::Failed)),
+    );
+
+    Ok(states)
+}
+
+/// Returns whether the target of a tracked file has been modified since it has
+/// been applied. Targets which do not exist anymore are not considered to be
+/// modified.
+pub fn locally_modified(e: &Entry) -> Result<bool> {
+    match e.kind {
+        EntryKind::Symlink => Ok(e.path.symlink_metadata().is_ok() && !is_symlink(&e.path)),
+        EntryKind::File => {
+            let Some(ref hash) = e.hash else {
+                return Ok(false);
+            };
+            match fs::read(&e.path) {
+                Ok(current) => Ok(filecache::hash(&current) != *hash),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+                Err(err) => Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Removes all directories created by dotrs which have become empty, deepest
+/// first. Directories which no longer exist are forgotten and directories which
+/// still contain files are kept tracked.
+pub fn prune_dirs(fc: &mut FileCache) {
+    let mut dirs = fc.dirs().clone();
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+    dirs.retain(|dir| match dir.read_dir().map(|mut d| d.next().is_none()) {
+        Ok(true) => match fs::remove_dir(dir) {
+            Ok(()) => {
+                debug!("delete {dir:?}/");
+                false
+            }
+            Err(err) => {
+                warn!("failed deleting empty directory {dir:?}: {err}");
+                true
+            }
+        },
+        Ok(false) => true,
+        Err(err) => err.kind() != ErrorKind::NotFound,
+    });
+    fc.set_dirs(dirs);
+}
+
+/// Removes a tracked file from its target location. When the target existed
+/// before it has been applied, the original file is restored instead. Symlinks
+/// are only removed when they are still links unless forced, so files which
+/// have been replaced by the user are left untouched. Targets which do not
+/// exist anymore are considered to be removed already.
+pub fn remove_entry(e: &Entry, force: bool) -> io::Result<()> {
+    match e.path.symlink_metadata() {
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            debug!("{:?} has already been removed", e.path);
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+        Ok(_) => {}
+    }
+
+    if !force && e.kind == EntryKind::Symlink && !is_symlink(&e.path) {
+        return Err(io::Error::other("target is no longer a symlink"));
+    }
+
+    match e.backup {
+        Some(ref backup_path) if is_symlink(backup_path) => {
+            link(&fs::read_link(backup_path)?, &e.path)?;
+            debug!("restored link {:?} from {backup_path:?}", e.path);
+            Ok(())
+        }
+        Some(ref backup_path) if backup_path.is_file() => {
+            let content = fs::read(backup_path)?;
+            write_atomic(&e.path, &content, file_mode(&fs::metadata(backup_path)?))?;
+            debug!("restored {:?} from {backup_path:?}", e.path);
+            Ok(())
+        }
+        _ => fs::remove_file(&e.path),
     }
+}
+
+/// Deletes the copy of the original file of a removed entry once it has been
+/// restored, so that it is not mistaken for the original of a later apply.
+pub fn discard_original(e: &Entry) {
+    let Some(ref backup_path) = e.backup else {
+        return;
+    };
+    if e.path.symlink_metadata().is_err() || backup_path.symlink_metadata().is_err() {
+        return;
+    }
+    match fs::remove_file(backup_path) {
+        Ok(()) => debug!("delete {backup_path:?}"),
+        Err(err) => warn!("failed deleting backup {backup_path:?}: {err}"),
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(meta: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_file_mode(p: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(p, fs::Permissions::from_mode(mode))
+}
 
+#[cfg(not(unix))]
+fn set_file_mode(_: &Path, _: u32) -> io::Result<()> {
     Ok(())
 }
 
+fn is_symlink(p: &Path) -> bool {
+    p.symlink_metadata()
+        .is_ok_and(|m| m.file_type().is_symlink())
+}
+
+fn is_link_to(link: &Path, target: &Path) -> bool {
+    fs::read_link(link).is_ok_and(|p| p == target)
+}
+
+/// Writes the content to a temporary file next to the target and renames it
+/// into place, so that the target is never left partially written.
+fn write_atomic(target: &Path, content: &[u8], mode: Option<u32>) -> io::Result<()> {
+    let mut tmp = tempfile::Builder::new()
+        .prefix(".dotrs-")
+        .tempfile_in(parent_dir(target))?;
+    tmp.write_all(content)?;
+    tmp.as_file().sync_all()?;
+    if let Some(mode) = mode {
+        set_file_mode(tmp.path(), mode)?;
+    }
+    tmp.persist(target)?;
+    Ok(())
+}
+
+/// Creates the link next to its final location and renames it into place, so
+/// that an existing file is only replaced once the link has been created.
+pub fn link(original: &Path, link: &Path) -> io::Result<()> {
+    let tmp = parent_dir(link).join(format!(
+        ".dotrs-{}-{}",
+        std::process::id(),
+        link.file_name().unwrap_or_default().to_string_lossy()
+    ));
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(original, &tmp)?;
+
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(original, &tmp)?;
+
+    fs::rename(&tmp, link).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+fn parent_dir(p: &Path) -> &Path {
+    match p.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Copies files from the home directory into the stage at their home-relative
+/// paths and registers them as applied. Directories are added recursively.
+pub fn add(cfg: &Config, path: &Path, template: bool) -> Result<Vec<Entry>> {
+    assert_stage_dir_initialized(cfg)?;
+
+    let stage_dir = path::absolute(&cfg.stage_dir)?;
+    let cache_dir = path::absolute(&cfg.cache_dir)?;
+    let path = path::absolute(path)?;
+
+    let repo_cfg = RepoConfig::load(&stage_dir)?;
+    let targets = target_map(cfg, &repo_cfg)?;
+    let ignore = stage_ignore(&stage_dir)?;
+
+    if targets.source_for(&path).is_none() {
+        anyhow::bail!("{path:?} is not located in any target directory");
+    }
+
+    let files = match path.is_dir() {
+        true => WalkBuilder::new(&path)
+            .hidden(false)
+            .add_custom_ignore_filename(".dotrsignore")
+            .filter_entry(walk_filter)
+            .build()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_some_and(|t| t.is_file()))
+            .map(|e| e.into_path())
+            .collect(),
+        false => vec![path],
+    };
+
+    let mut sources = vec![];
+
+    for f in files {
+        if f.starts_with(&stage_dir) || f.starts_with(&cache_dir) {
+            debug!("skipping {f:?} as it is managed by dotrs");
+            continue;
+        }
+
+        let Some(rel_path) = targets.source_for(&f) else {
+            continue;
+        };
+
+        let source = stage_dir.join(rel_path);
+        if ignore
+            .matched_path_or_any_parents(&source, false)
+            .is_ignore()
+        {
+            warn!("skipping {f:?} as it is ignored by .dotrsignore");
+            continue;
+        }
+
+        let mut source = source.into_os_string();
+        if template {
+            match repo_cfg.template_suffixes.first() {
+                Some(suffix) => source.push(suffix),
+                None => debug!("all files are rendered as templates"),
+            }
+        }
+
+        let source = PathBuf::from(source);
+        if source.exists() {
+            anyhow::bail!("{source:?} already exists in stage");
+        }
+
+        sources.push((f, source));
+    }
+
+    let state_dir = cfg.state_dir()?;
+    let profile = get_applied_profile(&state_dir)?;
+    let mut fc = FileCache::open(state_dir.join(NAME_FILECACHE))?;
+    let mut added = vec![];
+
+    for (f, source) in sources {
+        let content = fs::read(&f)?;
+
+        if !template
+            && repo_cfg.template_suffixes.is_empty()
+            && as_text(&content).is_some_and(|t| t.contains("{{"))
+        {
+            warn!("{f:?} contains template expressions which will be rendered on apply");
+        }
+
+        if let Some(parent) = source.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&f, &source)?;
+        debug!("copied {f:?} -> {source:?}");
+
+        let entry = Entry {
+            mode: file_mode(&f.metadata()?),
+            hash: Some(filecache::hash(&content)),
+            path: f,
+            kind: EntryKind::File,
+            templated: template,
+            source: Some(source),
+            profile: profile.clone(),
+            applied_at: Some(filecache::timestamp()),
+            backup: None,
+        };
+        fc.insert(entry.clone());
+        added.push(entry);
+    }
+
+    fc.store()?;
+
+    Ok(added)
+}
+
+/// Builds a matcher for the patterns in the `.dotrsignore` file in the root of
+/// the stage.
+fn stage_ignore(stage_dir: &Path) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(stage_dir);
+    let ignore_file = stage_dir.join(".dotrsignore");
+    if ignore_file.exists() {
+        if let Some(err) = builder.add(ignore_file) {
+            return Err(err.into());
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// Stops managing the tracked files at or below the given path. Their sources
+/// are removed from the stage and the removal is committed, while the files in
+/// the home directory are left in place.
+pub fn forget(cfg: &Config, path: &Path, author: impl AsRef<str>) -> Result<Vec<Entry>> {
+    assert_stage_dir_initialized(cfg)?;
+
+    let stage_dir = path::absolute(&cfg.stage_dir)?;
+    let path = path::absolute(path)?;
+
+    let mut fc = FileCache::open(cfg.state_dir()?.join(NAME_FILECACHE))?;
+    let (forgotten, kept): (Vec<_>, Vec<_>) = fc
+        .get()
+        .iter()
+        .cloned()
+        .partition(|e| e.path.starts_with(&path));
+
+    if forgotten.is_empty() {
+        anyhow::bail!("{path:?} is not managed by dotrs");
+    }
+
+    let targets = target_map(cfg, &RepoConfig::load(&stage_dir)?)?;
+
+    let mut sources = vec![];
+    for e in &forgotten {
+        let source = match e.source {
+            Some(ref source) => source.clone(),
+            None => match targets.source_for(&e.path) {
+                Some(rel_path) => stage_dir.join(rel_path),
+                None => continue,
+            },
+        };
+
+        // Links into the stage would dangle after the source has been
+        // removed, so they are replaced with a copy of the source.
+        if e.kind == EntryKind::Symlink && is_symlink(&e.path) {
+            fs::remove_file(&e.path)?;
+            fs::copy(&source, &e.path)?;
+            debug!("replaced link {:?} with copy of {source:?}", e.path);
+        }
+
+        sources.push(source);
+    }
+
+    fc.set(kept);
+    fc.store()?;
+
+    let git = Git::new(&stage_dir);
+    let mut committed = vec![];
+
+    for source in sources.iter().filter(|s| s.exists()) {
+        match git.exec([
+            OsStr::new("ls-files"),
+            OsStr::new("--error-unmatch"),
+            source.as_os_str(),
+        ]) {
+            Ok(_) => {
+                git.exec([
+                    OsStr::new("rm"),
+                    OsStr::new("--quiet"),
+                    OsStr::new("--force"),
+                    source.as_os_str(),
+                ])?;
+                committed.push(source.as_os_str());
+            }
+            Err(git::Error::NonZeroExit { .. }) => fs::remove_file(source)?,
+            Err(err) => return Err(err.into()),
+        }
+        debug!("removed {source:?} from stage");
+        remove_empty_parents(source, &stage_dir)?;
+    }
+
+    if !committed.is_empty() {
+        let names = sources
+            .iter()
+            .filter_map(|s| s.strip_prefix(&stage_dir).ok())
+            .map(|s| s.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let message = format!("forget: {names}");
+
+        let mut args = vec![
+            OsStr::new("commit"),
+            OsStr::new("--message"),
+            OsStr::new(&message),
+            OsStr::new("--author"),
+            OsStr::new(author.as_ref()),
+            OsStr::new("--"),
+        ];
+        args.extend(committed);
+        git.exec(args)?;
+    }
+
+    Ok(forgotten)
+}
+
+/// Removes the parent directories of the given path up to the root directory
+/// as long as they are empty.
+fn remove_empty_parents(path: &Path, root: &Path) -> Result<()> {
+    for dir in path.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) || dir.read_dir()?.next().is_some() {
+            break;
+        }
+        debug!("delete {dir:?}/");
+        fs::remove_dir(dir)?;
+    }
+    Ok(())
+}
+
+pub enum ReAddOutcome {
+    /// The target has been copied back to the stage.
+    Copied,
+    /// The changes of the target have been merged into the template in stage.
+    Merged,
+    /// The changes could not be captured for the given reason.
+    Refused(&'static str),
+}
+
+pub struct ReAdded {
+    pub target: PathBuf,
+    pub source: Option<PathBuf>,
+    pub outcome: ReAddOutcome,
+}
+
+/// Captures changes made to tracked files in the home directory back into
+/// their source files in stage.
+pub fn re_add(cfg: &Config) -> Result<Vec<ReAdded>> {
+    assert_stage_dir_initialized(cfg)?;
+
+    let state_dir = cfg.state_dir()?;
+    let profile = get_applied_profile(&state_dir)?;
+    let renderer = Renderer::new(cfg, profile.as_deref())?;
+
+    let mut fc = FileCache::open(state_dir.join(NAME_FILECACHE))?;
+    let mut entries = fc.get().clone();
+    let mut results = vec![];
+
+    for e in entries.iter_mut() {
+        if e.kind != EntryKind::File || !locally_modified(e)? {
+            continue;
+        }
+
+        let outcome = match e.source {
+            Some(ref source) if source.exists() => {
+                let current = fs::read(&e.path)?;
+                let outcome = re_add_file(&renderer, source, &current, e.hash.as_deref())?;
+                if !matches!(outcome, ReAddOutcome::Refused(_)) {
+                    e.hash = Some(filecache::hash(&current));
+                }
+                outcome
+            }
+            Some(_) => ReAddOutcome::Refused("source file has been removed from stage"),
+            None => ReAddOutcome::Refused("source file in stage is unknown"),
+        };
+
+        results.push(ReAdded {
+            target: e.path.clone(),
+            source: e.source.clone(),
+            outcome,
+        });
+    }
+
+    fc.set(entries);
+    fc.store()?;
+
+    Ok(results)
+}
+
+fn re_add_file(
+    renderer: &Renderer,
+    source: &Path,
+    current: &[u8],
+    applied_hash: Option<&str>,
+) -> Result<ReAddOutcome> {
+    let rendered = renderer.render(source)?;
+
+    if !rendered.templated {
+        debug!("copy {:?} -> {source:?}", rendered.target);
+        fs::write(source, current)?;
+        return Ok(ReAddOutcome::Copied);
+    }
+
+    if applied_hash != Some(filecache::hash(&rendered.content).as_str()) {
+        return Ok(ReAddOutcome::Refused(
+            "template in stage has changed since it has been applied",
+        ));
+    }
+
+    let template = fs::read(source)?;
+    let patched = match (
+        as_text(&template),
+        as_text(&rendered.content),
+        as_text(current),
+    ) {
+        (Some(template), Some(rendered), Some(current)) => {
+            diff::patch_template(template, rendered, current)
+        }
+        _ => None,
+    };
+
+    let Some(patched) = patched else {
+        return Ok(ReAddOutcome::Refused(
+            "changes can not be mapped cleanly to the template",
+        ));
+    };
+
+    fs::write(source, &patched)?;
+
+    // Make sure that the patched template renders exactly to the
+    // modified file; otherwise, restore the original template.
+    if renderer.render(source)?.content != current {
+        fs::write(source, &template)?;
+        return Ok(ReAddOutcome::Refused(
+            "changes can not be mapped cleanly to the template",
+        ));
+    }
+
+    debug!("merged {:?} -> {source:?}", rendered.target);
+    Ok(ReAddOutcome::Merged)
+}
+
 pub fn pull(cfg: &Config) -> Result<()> {
     assert_stage_dir_initialized(cfg)?;
 
@@ -72,7 +1125,15 @@ pub fn update(
 
     git.exec(["pull", "origin", &branch])?;
 
-    match git.exec(["diff", "--quiet", "--exit-code"]) {
+    git.exec(["add", "."])?;
+
+    match git.exec(["diff", "--cached", "--quiet", "--exit-code"]) {
+        // Commits which have been made locally (i.e. by forget) are
+        // published even if there are no other changes.
+        Ok(_) if git.commits_ahead(&branch)? > 0 => {
+            git.exec(["push", "origin", &branch])?;
+            return Ok(true);
+        }
         Ok(_) => return Ok(false),
         Err(git::Error::NonZeroExit {
             code: 1,
@@ -81,8 +1142,6 @@ pub fn update(
         Err(err) => return Err(err.into()),
     }
 
-    git.exec(["add", "."])?;
-
     let message = match message {
         Some(ref s) => s.as_ref(),
         None => {
@@ -113,50 +1172,162 @@ pub fn assert_stage_dir_initialized(cfg: &Config) -> Result<()> {
     Ok(())
 }
 
-fn apply_recursively(
-    from: impl AsRef<Path>,
-    to: impl AsRef<Path>,
-    profile: Option<&str>,
-) -> Result<Vec<PathBuf>> {
-    let walker = WalkBuilder::new(&from)
-        .hidden(false)
-        .add_custom_ignore_filename(".dotrsignore")
-        .filter_entry(walk_filter)
-        .build();
+/// Resolves the target directories of the repository for the configured target
+/// directory.
+fn target_map(cfg: &Config, repo_cfg: &RepoConfig) -> Result<TargetMap> {
+    let target_dir = match cfg.target_dir {
+        Some(_) => Some(cfg.target_dir()?),
+        None => None,
+    };
+    repo_cfg.target_map(&super::home_dir()?, target_dir.as_deref())
+}
 
-    let mut copied_files = vec![];
+/// Renders files from the stage with the data of a profile.
+pub struct Renderer {
+    stage_dir: PathBuf,
+    targets: TargetMap,
+    repo_cfg: RepoConfig,
+    mode_overrides: ModeOverrides,
+    hb: Handlebars<'static>,
+    data: Value,
+}
 
-    let mut hb = Handlebars::new();
-    hb.set_strict_mode(true);
+impl Renderer {
+    pub fn new(cfg: &Config, profile: Option<&str>) -> Result<Self> {
+        let stage_dir = path::absolute(&cfg.stage_dir)?;
 
-    let data = match profile {
-        Some(profile) => Profile::new(from.as_ref(), profile != "default").load(profile)?,
-        None => Value::None,
-    };
+        let repo_cfg = RepoConfig::load(&stage_dir)?;
+        let targets = target_map(cfg, &repo_cfg)?;
+        let mode_overrides = repo_cfg.mode_overrides()?;
 
-    let mut buf = String::new();
+        let mut hb = Handlebars::new();
+        hb.set_strict_mode(true);
 
-    for entry in walker {
-        let entry = entry?;
-        let path = entry.path();
-        let to_path = to.as_ref().join(path.strip_prefix(&from)?);
+        let data = Profile::new(&stage_dir).load(profile.unwrap_or(DEFAULT_PROFILE))?;
 
-        if path.metadata()?.is_dir() {
-            if !to_path.exists() {
-                fs::create_dir_all(&to_path)?;
+        let mut renderer = Self {
+            stage_dir,
+            targets,
+            repo_cfg,
+            mode_overrides,
+            hb,
+            data,
+        };
+        renderer.set_var(FACTS_KEY, Facts::collect(&cfg.target_dir()?)?.to_value());
+
+        Ok(renderer)
+    }
+
+    /// Returns all files in the stage which are applied.
+    pub fn stage_files(&self) -> Result<Vec<PathBuf>> {
+        let walker = WalkBuilder::new(&self.stage_dir)
+            .hidden(false)
+            .add_custom_ignore_filename(".dotrsignore")
+            .filter_entry(walk_filter)
+            .build();
+
+        let mut files = vec![];
+
+        for entry in walker {
+            let entry = entry?;
+            if entry.path().metadata()?.is_dir() {
+                continue;
             }
-        } else {
-            buf.clear();
-            File::open(path)?.read_to_string(&mut buf)?;
-            let rendered = hb.render_template(&buf, &data)?;
-            File::create(&to_path)?.write_all(rendered.as_bytes())?;
+            files.push(entry.into_path());
+        }
+
+        Ok(files)
+    }
 
-            copied_files.push(to_path.to_owned());
-            debug!("copied {path:?} -> {to_path:?}");
+    /// Sets a variable in the profile data, overriding the value of the profile.
+    /// Nested variables can be addressed with dots (i.e. `git.email`).
+    pub fn set_var(&mut self, key: &str, value: Value) {
+        let mut data = &mut self.data;
+        for part in key.split('.') {
+            if !matches!(data, Value::Map(_)) {
+                *data = Value::Map(HashMap::new());
+            }
+            let Value::Map(map) = data else {
+                unreachable!();
+            };
+            data = map.entry(part.to_owned()).or_insert(Value::None);
         }
+        *data = value;
+    }
+
+    /// Renders a template under the name of its file in the stage, so that
+    /// errors point to the file.
+    fn render_template(&self, name: &Path, template: &str) -> Result<String> {
+        let tpl = Template::compile_with_name(template, name.to_string_lossy().into_owned())?;
+        let ctx = Context::wraps(&self.data)?;
+        let mut rc = RenderContext::new(tpl.name.as_ref());
+        let mut out = StringOutput::new();
+        tpl.render(&self.hb, &ctx, &mut rc, &mut out)?;
+        Ok(out.into_string()?)
+    }
+
+    /// Returns whether a file from the stage is a template and its target.
+    pub fn target_for(&self, path: &Path) -> Result<(bool, PathBuf)> {
+        let rel_path = path.strip_prefix(&self.stage_dir)?;
+        Ok(self
+            .repo_cfg
+            .template_target(&self.targets.target_for(rel_path)))
+    }
+
+    /// Returns whether the mode of a file from the stage is forced in the
+    /// repository config.
+    pub fn has_mode_override(&self, path: &Path) -> Result<bool> {
+        let rel_path = path.strip_prefix(&self.stage_dir)?;
+        Ok(self.mode_overrides.get(rel_path).is_some())
+    }
+
+    /// Renders a single file from the stage.
+    pub fn render(&self, path: &Path) -> Result<RenderedFile> {
+        let meta = path.metadata()?;
+
+        let rel_path = path.strip_prefix(&self.stage_dir)?;
+        let (is_template, target) = self.target_for(path)?;
+        let mode = self
+            .mode_overrides
+            .get(rel_path)
+            .or_else(|| file_mode(&meta));
+
+        let raw = fs::read(path)?;
+        let (content, templated) = match as_text(&raw) {
+            Some(text) if is_template => {
+                let content = self.render_template(rel_path, text)?;
+                let templated = content != text || target.file_name() != path.file_name();
+                debug!("rendered {path:?}");
+                (content.into_bytes(), templated)
+            }
+            Some(_) => {
+                debug!("copied {path:?} without templating");
+                (raw, false)
+            }
+            None => {
+                debug!("binary file {path:?} is copied without templating");
+                (raw, false)
+            }
+        };
+
+        Ok(RenderedFile {
+            source: path.to_owned(),
+            target,
+            content,
+            templated,
+            kind: EntryKind::File,
+            mode,
+        })
     }
+}
 
-    Ok(copied_files)
+/// Returns the content as text if it is valid UTF-8 and does not contain any
+/// NUL bytes. Otherwise, the content is considered to be binary.
+pub fn as_text(content: &[u8]) -> Option<&str> {
+    match std::str::from_utf8(content) {
+        Ok(text) if !text.contains('\0') => Some(text),
+        _ => None,
+    }
 }
 
 fn walk_filter(de: &DirEntry) -> bool {
@@ -166,6 +1337,9 @@ fn walk_filter(de: &DirEntry) -> bool {
 
     match meta.is_dir() {
         true => !de.path().ends_with(".git") && !de.path().ends_with(".dotrs-profiles"),
+        false if de.depth() == 1 && REPO_CONFIG_FILES.iter().any(|f| de.path().ends_with(f)) => {
+            false
+        }
         false => !de.path().ends_with(".gitignore"),
     }
 }
@@ -175,6 +1349,8 @@ fn change_to_string((mode, filename): &(Change, String)) -> String {
         Change::Modified => "update",
         Change::Added => "add",
         Change::Deleted => "remove",
+        Change::Renamed => "rename",
+        Change::Untracked => "add",
     };
     format!("{prefix} {filename}")
 }
@@ -26,6 +26,9 @@ pub enum Error {
 
     #[error("invalid change line: {0}")]
     InvalidChangeLine(String),
+
+    #[error("invalid command output: {0}")]
+    InvalidOutput(String),
 }
 
 pub struct CommandOutput(Output);
@@ -49,16 +52,37 @@ pub enum Change {
     Modified,
     Added,
     Deleted,
+    Renamed,
+    Untracked,
+}
+
+impl Change {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Change::Modified => "modified",
+            Change::Added => "added",
+            Change::Deleted => "deleted",
+            Change::Renamed => "renamed",
+            Change::Untracked => "untracked",
+        }
+    }
 }
 
 impl FromStr for Change {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Porcelain status codes consist of the state in the index and in the
+        // work tree, of which the first one which is set is used.
         match s {
-            "M" => Ok(Change::Modified),
-            "A" => Ok(Change::Added),
-            "D" => Ok(Change::Deleted),
+            "??" => return Ok(Change::Untracked),
+            s if s.starts_with('R') => return Ok(Change::Renamed),
+            _ => {}
+        }
+        match s.chars().next() {
+            Some('M') => Ok(Change::Modified),
+            Some('A') => Ok(Change::Added),
+            Some('D') => Ok(Change::Deleted),
             _ => Err(Error::InvalidChangeType(s.to_owned())),
         }
     }
@@ -122,6 +146,42 @@ impl Git {
         Ok(branch.trim().to_owned())
     }
 
+    /// Returns the number of local commits which have not been pushed to the
+    /// given branch of the origin remote.
+    pub fn commits_ahead(&self, branch: &str) -> Result<usize, Error> {
+        let out = self.exec(["rev-list", "--count", &format!("origin/{branch}..HEAD")])?;
+        let count = out.stdout()?;
+        count
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidOutput(count))
+    }
+
+    /// Returns the number of commits on the given branch of the origin remote
+    /// which have not been merged locally.
+    pub fn commits_behind(&self, branch: &str) -> Result<usize, Error> {
+        let out = self.exec(["rev-list", "--count", &format!("HEAD..origin/{branch}")])?;
+        let count = out.stdout()?;
+        count
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidOutput(count))
+    }
+
+    /// Returns a one-line summary of the last commit which touched the given
+    /// path, if any.
+    pub fn last_commit<P: AsRef<OsStr>>(&self, path: P) -> Result<Option<String>, Error> {
+        let out = self.exec([
+            OsStr::new("log"),
+            OsStr::new("-1"),
+            OsStr::new("--format=%h %s (%an, %ar)"),
+            OsStr::new("--"),
+            path.as_ref(),
+        ])?;
+        let commit = out.stdout()?;
+        Ok(Some(commit.trim().to_owned()).filter(|c| !c.is_empty()))
+    }
+
     pub fn changed_files(&self) -> Result<Vec<(Change, String)>, Error> {
         let changes = self.exec(["status", "--porcelain"])?;
         changes.stdout()?.lines().map(parse_change).collect()
@@ -0,0 +1,41 @@
+use anyhow::Result;
+use log::info;
+use std::fs::{self, File, TryLockError};
+use std::path::Path;
+
+pub const LOCK_FILE: &str = ".dotrs.lock";
+
+/// An advisory lock on the stage and cache directory which is held while an
+/// operation modifies them. The lock is released when it is dropped.
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    /// Acquires the lock in the given cache directory. When the lock is held by
+    /// another process, this either fails or blocks until it has been released.
+    pub fn acquire<P: AsRef<Path>>(cache_dir: P, wait: bool) -> Result<Self> {
+        let cache_dir = cache_dir.as_ref();
+        fs::create_dir_all(cache_dir)?;
+
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(cache_dir.join(LOCK_FILE))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) if wait => {
+                info!("Waiting for another dotrs operation to finish ...");
+                file.lock()?;
+            }
+            Err(TryLockError::WouldBlock) => anyhow::bail!(
+                "another dotrs operation is in progress; use --wait to wait for it to finish"
+            ),
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+
+        Ok(Self { _file: file })
+    }
+}
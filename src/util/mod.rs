@@ -1,5 +1,8 @@
+pub mod backup;
+pub mod diff;
 pub mod dotfiles;
 pub mod git;
+pub mod lock;
 
 use anyhow::Result;
 use std::path::PathBuf;